@@ -0,0 +1,11 @@
+#!/bin/sh
+
+# Runs a node binary locally, e.g. `sh run-router.sh efficient-broadcast --topology tree`.
+# Clients connect to 127.0.0.1:7000.
+
+bin=$1
+shift
+
+cargo build
+
+./target/debug/router "$@" "target/debug/$bin"
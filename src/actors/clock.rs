@@ -0,0 +1,156 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::oneshot;
+
+/// The time source for periodic tasks, request timeouts and retries.
+#[async_trait::async_trait]
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> Instant;
+
+    async fn sleep_until(&self, deadline: Instant);
+
+    async fn sleep(&self, duration: Duration) {
+        self.sleep_until(self.now() + duration).await
+    }
+}
+
+/// The real clock, as seen by tokio. Follows tokio's paused clock in tests.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+#[async_trait::async_trait]
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        tokio::time::sleep_until(deadline.into()).await
+    }
+}
+
+/// A clock that only moves when [`ManualClock::advance`] is called.
+pub struct ManualClock {
+    start: Instant,
+    state: Mutex<ManualState>,
+}
+
+struct ManualState {
+    elapsed: Duration,
+    sleepers: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            state: Mutex::new(ManualState {
+                elapsed: Duration::ZERO,
+                sleepers: Vec::new(),
+            }),
+        }
+    }
+
+    /// Moves the clock forward and wakes everything that was due by then,
+    /// earliest deadline first.
+    pub fn advance(&self, by: Duration) {
+        let mut state = self.state.lock().expect("clock lock poisoned");
+        state.elapsed += by;
+
+        let now = self.start + state.elapsed;
+        let (mut due, waiting): (Vec<_>, _) = std::mem::take(&mut state.sleepers)
+            .into_iter()
+            .partition(|(deadline, _)| *deadline <= now);
+        state.sleepers = waiting;
+        drop(state);
+
+        due.sort_by_key(|(deadline, _)| *deadline);
+        for (_, tx) in due {
+            let _ = tx.send(());
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.state.lock().expect("clock lock poisoned").elapsed
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        let rx = {
+            let mut state = self.state.lock().expect("clock lock poisoned");
+            if deadline <= self.start + state.elapsed {
+                return;
+            }
+
+            // forget sleepers that gave up waiting
+            state.sleepers.retain(|(_, tx)| !tx.is_closed());
+
+            let (tx, rx) = oneshot::channel();
+            state.sleepers.push((deadline, tx));
+            rx
+        };
+
+        let _ = rx.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, sync::Arc};
+
+    #[tokio::test]
+    async fn advance_wakes_sleepers_in_deadline_order() {
+        let clock = Arc::new(ManualClock::new());
+        let woken = Arc::new(Mutex::new(Vec::new()));
+
+        let mut sleepers = HashMap::new();
+        for ms in [30, 10, 40, 20] {
+            let (clock, woken) = (clock.clone(), woken.clone());
+            let sleeper = tokio::spawn(async move {
+                clock.sleep(Duration::from_millis(ms)).await;
+                woken.lock().unwrap().push(ms);
+            });
+            sleepers.insert(ms, sleeper);
+        }
+        // let every sleeper register before the clock moves
+        for _ in 0..4 {
+            tokio::task::yield_now().await;
+        }
+
+        clock.advance(Duration::from_millis(30));
+        for ms in [10, 20, 30] {
+            sleepers.remove(&ms).unwrap().await.unwrap();
+        }
+        assert_eq!(*woken.lock().unwrap(), [10, 20, 30]);
+
+        clock.advance(Duration::from_millis(9));
+        tokio::task::yield_now().await;
+        assert_eq!(woken.lock().unwrap().len(), 3);
+
+        clock.advance(Duration::from_millis(1));
+        sleepers.remove(&40).unwrap().await.unwrap();
+        assert_eq!(*woken.lock().unwrap(), [10, 20, 30, 40]);
+    }
+
+    #[tokio::test]
+    async fn sleeping_until_a_past_deadline_returns_at_once() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        clock.advance(Duration::from_secs(1));
+
+        clock.sleep_until(start).await;
+        clock.sleep(Duration::ZERO).await;
+        assert_eq!(clock.now() - start, Duration::from_secs(1));
+    }
+}
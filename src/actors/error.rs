@@ -0,0 +1,41 @@
+use std::io;
+
+/// Failures of the node's IO pipeline that do not stop it by themselves.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Serialize(serde_json::Error),
+    /// The writer stopped before it was handed a message.
+    WriterStopped,
+}
+
+impl Error {
+    /// Whether the node can no longer talk to the outside world.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Io(_) | Self::WriterStopped)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Serialize(error)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Serialize(e) => write!(f, "failed to serialize message: {e}"),
+            Self::WriterStopped => write!(f, "the writer has stopped"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,363 @@
+use super::is_client;
+use crate::{maelstrom_protocol, rng::Rng};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The environment variable holding the fault rules a node starts with.
+pub const FAULTS_ENV: &str = "GLOMERS_FAULTS";
+
+/// The environment variable that, when set, lets clients switch a running
+/// node's fault rules with `set_faults`.
+pub const FAULT_CONTROL_ENV: &str = "GLOMERS_FAULT_CONTROL";
+
+/// The environment variable holding the seed faults are drawn with, so a
+/// faulty run can be repeated.
+pub const FAULT_SEED_ENV: &str = "GLOMERS_FAULT_SEED";
+
+/// What happens to an outgoing message a rule matches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Lost with the given probability.
+    Drop(f64),
+    /// Held back for a duration in `min..=max`.
+    Delay(Duration, Duration),
+    /// Written twice with the given probability.
+    Duplicate(f64),
+    /// With the given probability, written after the message that follows it.
+    Reorder(f64),
+    /// Always lost, as in a partition.
+    Block,
+}
+
+/// A fault and the messages it applies to. Messages to clients are never
+/// faulted, so the workload still hears back from nodes that got its request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub action: Action,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// The message `type`.
+    pub kind: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleError(String);
+
+impl std::fmt::Display for RuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid fault rule: {}", self.0)
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+impl Rule {
+    /// Parses rules separated by `;`, each an action followed by filters:
+    ///
+    /// ```text
+    /// drop 0.1 to=n2; delay 10ms 200ms; duplicate 0.05 type=gossip; reorder 0.2; block n1 n2
+    /// ```
+    ///
+    /// `block A B` cuts messages between `A` and `B` both ways. Filters are
+    /// `from=ID`, `to=ID` and `type=KIND`.
+    pub fn parse_all(spec: &str) -> Result<Vec<Self>, RuleError> {
+        let mut rules = Vec::new();
+        for rule in spec.split(';').filter(|rule| !rule.trim().is_empty()) {
+            let mut words = rule.split_whitespace();
+            let invalid = || RuleError(rule.trim().to_string());
+
+            let mut action_args = Vec::new();
+            let mut rule = Self {
+                action: Action::Block,
+                from: None,
+                to: None,
+                kind: None,
+            };
+            let action = words.next().ok_or_else(invalid)?;
+            for word in words {
+                match word.split_once('=') {
+                    Some(("from", id)) => rule.from = Some(id.to_string()),
+                    Some(("to", id)) => rule.to = Some(id.to_string()),
+                    Some(("type", kind)) => rule.kind = Some(kind.to_string()),
+                    Some(_) => return Err(invalid()),
+                    None => action_args.push(word),
+                }
+            }
+
+            let probability = |args: &[&str]| match args {
+                [p] => p
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|p| (0.0..=1.0).contains(p))
+                    .ok_or_else(invalid),
+                _ => Err(invalid()),
+            };
+            rule.action = match action {
+                "drop" => Action::Drop(probability(&action_args)?),
+                "duplicate" => Action::Duplicate(probability(&action_args)?),
+                "reorder" => Action::Reorder(probability(&action_args)?),
+                "delay" => match action_args[..] {
+                    [min, max] => {
+                        let min = parse_duration(min).ok_or_else(invalid)?;
+                        let max = parse_duration(max).ok_or_else(invalid)?;
+                        Action::Delay(min, max.max(min))
+                    }
+                    [delay] => {
+                        let delay = parse_duration(delay).ok_or_else(invalid)?;
+                        Action::Delay(delay, delay)
+                    }
+                    _ => return Err(invalid()),
+                },
+                "block" => match action_args[..] {
+                    [a, b] => {
+                        let mut back = rule.clone();
+                        back.from = Some(b.to_string());
+                        back.to = Some(a.to_string());
+                        rules.push(back);
+
+                        rule.from = Some(a.to_string());
+                        rule.to = Some(b.to_string());
+                        Action::Block
+                    }
+                    _ => return Err(invalid()),
+                },
+                _ => return Err(invalid()),
+            };
+            rules.push(rule);
+        }
+
+        Ok(rules)
+    }
+
+    fn matches(&self, src: &str, dst: &str, buf: &[u8]) -> bool {
+        self.from.as_ref().is_none_or(|from| from == src)
+            && self.to.as_ref().is_none_or(|to| to == dst)
+            && self.kind.as_ref().is_none_or(|kind| {
+                serde_json::from_slice::<maelstrom_protocol::Envelope>(buf)
+                    .and_then(|envelope| envelope.header())
+                    .is_ok_and(|header| header.kind.as_ref() == Some(kind))
+            })
+    }
+}
+
+/// Switches the writer's fault rules, replacing the ones it had.
+pub struct SetFaults(pub Vec<Rule>);
+
+impl xtra::Message for SetFaults {
+    type Result = ();
+}
+
+/// Switches a running node's fault rules over the wire. When fault control is
+/// on, the runtime answers these from clients itself, before the node sees them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub(crate) enum FaultControl {
+    SetFaults { rules: String },
+    SetFaultsOk,
+}
+
+impl maelstrom_protocol::Payload for FaultControl {}
+
+/// What the writer does with one outgoing message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Fault {
+    None,
+    Drop,
+    Delay(Duration),
+    Duplicate,
+    Reorder,
+}
+
+/// The rules a writer applies, and the randomness behind them.
+pub(crate) struct Faults {
+    rules: Vec<Rule>,
+    rng: Rng,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self::new(Vec::new(), 0)
+    }
+}
+
+impl Faults {
+    pub(crate) fn new(rules: Vec<Rule>, seed: u64) -> Self {
+        Self {
+            rules,
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub(crate) fn set_rules(&mut self, rules: Vec<Rule>) {
+        self.rules = rules;
+    }
+
+    /// The first rule that matches and fires decides the message's fate.
+    pub(crate) fn decide(&mut self, src: &str, dst: &str, buf: &[u8]) -> Fault {
+        if is_client(dst) {
+            return Fault::None;
+        }
+
+        for rule in &self.rules {
+            if !rule.matches(src, dst, buf) {
+                continue;
+            }
+
+            let fault = match rule.action {
+                Action::Block => Fault::Drop,
+                Action::Drop(p) if self.rng.chance(p) => Fault::Drop,
+                Action::Delay(min, max) => Fault::Delay(self.rng.duration(min, max)),
+                Action::Duplicate(p) if self.rng.chance(p) => Fault::Duplicate,
+                Action::Reorder(p) if self.rng.chance(p) => Fault::Reorder,
+                _ => continue,
+            };
+            return fault;
+        }
+
+        Fault::None
+    }
+}
+
+/// Reads `250ms`, `2s` or `1.5s`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    if let Some(ms) = s.strip_suffix("ms") {
+        return ms.parse().ok().map(Duration::from_millis);
+    }
+    let secs = s.strip_suffix('s')?.parse().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(action: Action, from: Option<&str>, to: Option<&str>, kind: Option<&str>) -> Rule {
+        Rule {
+            action,
+            from: from.map(str::to_string),
+            to: to.map(str::to_string),
+            kind: kind.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        let ms = Duration::from_millis;
+        for (spec, expected) in [
+            ("drop 0.1", vec![rule(Action::Drop(0.1), None, None, None)]),
+            (
+                "duplicate 1 type=gossip",
+                vec![rule(Action::Duplicate(1.0), None, None, Some("gossip"))],
+            ),
+            (
+                "reorder 0.2 from=n1 to=n2",
+                vec![rule(Action::Reorder(0.2), Some("n1"), Some("n2"), None)],
+            ),
+            (
+                "delay 10ms 1.5s",
+                vec![rule(Action::Delay(ms(10), ms(1500)), None, None, None)],
+            ),
+            // a range given backwards is a fixed delay
+            (
+                "delay 2s 1s",
+                vec![rule(Action::Delay(ms(2000), ms(2000)), None, None, None)],
+            ),
+            (
+                "delay 250ms",
+                vec![rule(Action::Delay(ms(250), ms(250)), None, None, None)],
+            ),
+            (
+                "block n1 n2 type=gossip",
+                vec![
+                    rule(Action::Block, Some("n2"), Some("n1"), Some("gossip")),
+                    rule(Action::Block, Some("n1"), Some("n2"), Some("gossip")),
+                ],
+            ),
+            (
+                " drop 0.5 to=n3;; reorder 0 ",
+                vec![
+                    rule(Action::Drop(0.5), None, Some("n3"), None),
+                    rule(Action::Reorder(0.0), None, None, None),
+                ],
+            ),
+            ("", vec![]),
+        ] {
+            assert_eq!(Rule::parse_all(spec).unwrap(), expected, "{spec}");
+        }
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for spec in [
+            "explode",
+            "drop",
+            "drop 1.5",
+            "drop -0.1",
+            "drop x",
+            "drop 0.1 0.2",
+            "drop 0.1 via=n1",
+            "delay",
+            "delay 10",
+            "delay 10ms 20ms 30ms",
+            "block n1",
+            "block n1 n2 n3",
+            "drop 0.1; nonsense",
+        ] {
+            assert!(Rule::parse_all(spec).is_err(), "{spec}");
+        }
+    }
+
+    const GOSSIP: &[u8] = br#"{"src":"n1","dest":"n2","body":{"type":"gossip"}}"#;
+    const READ: &[u8] = br#"{"src":"n1","dest":"n2","body":{"type":"read"}}"#;
+
+    fn seeded(spec: &str) -> Faults {
+        Faults::new(Rule::parse_all(spec).unwrap(), 0)
+    }
+
+    #[test]
+    fn rules_apply_to_the_messages_they_match() {
+        let mut faults = seeded("block n1 n2 type=gossip");
+        assert_eq!(faults.decide("n1", "n2", GOSSIP), Fault::Drop);
+        assert_eq!(faults.decide("n2", "n1", GOSSIP), Fault::Drop);
+        assert_eq!(faults.decide("n1", "n2", READ), Fault::None);
+        assert_eq!(faults.decide("n1", "n3", GOSSIP), Fault::None);
+
+        let mut faults = seeded("delay 10ms; drop 1");
+        assert_eq!(
+            faults.decide("n1", "n2", READ),
+            Fault::Delay(Duration::from_millis(10))
+        );
+    }
+
+    #[test]
+    fn the_first_rule_that_fires_wins() {
+        let mut faults = seeded("drop 0; duplicate 1; reorder 1");
+        assert_eq!(faults.decide("n1", "n2", READ), Fault::Duplicate);
+
+        faults.set_rules(Vec::new());
+        assert_eq!(faults.decide("n1", "n2", READ), Fault::None);
+    }
+
+    #[test]
+    fn clients_are_never_faulted() {
+        let mut faults = seeded("block n1 c1; drop 1");
+        assert_eq!(faults.decide("n1", "c1", READ), Fault::None);
+        assert_eq!(faults.decide("n1", "n2", READ), Fault::Drop);
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_faults() {
+        let decisions = |seed| {
+            let mut faults = Faults::new(Rule::parse_all("drop 0.5").unwrap(), seed);
+            (0..64)
+                .map(|_| faults.decide("n1", "n2", READ))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(decisions(7), decisions(7));
+        assert_ne!(decisions(7), decisions(8));
+        assert!(decisions(7).contains(&Fault::Drop));
+        assert!(decisions(7).contains(&Fault::None));
+    }
+}
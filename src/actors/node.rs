@@ -0,0 +1,165 @@
+use super::{rpc, service, Clock, Output, RpcClient, Sender};
+use crate::maelstrom_protocol;
+use std::sync::Arc;
+
+/// Identity of the running node, known once the `init` handshake is done.
+#[derive(Debug, Clone)]
+pub struct NodeContext {
+    pub node_id: String,
+    /// Every node in the cluster, in the order `init` listed them.
+    pub node_ids: Vec<String>,
+}
+
+impl NodeContext {
+    /// All other nodes in the cluster, in the order `init` listed them.
+    pub fn peers(&self) -> impl Iterator<Item = &String> {
+        self.node_ids.iter().filter(move |n| **n != self.node_id)
+    }
+}
+
+/// Whether `id` names a Maelstrom client, such as `c4`.
+pub fn is_client(id: &str) -> bool {
+    id.strip_prefix('c')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+impl From<maelstrom_protocol::InitPayload> for NodeContext {
+    fn from(init: maelstrom_protocol::InitPayload) -> Self {
+        Self {
+            node_id: init.node_id,
+            node_ids: init.node_ids,
+        }
+    }
+}
+
+/// A message delivered to the node actor together with its handler context.
+pub struct Input<P: maelstrom_protocol::Payload + 'static>(
+    pub maelstrom_protocol::Message<P>,
+    pub HandlerContext<P>,
+);
+
+impl<P: maelstrom_protocol::Payload + 'static> xtra::Message for Input<P> {
+    type Result = ();
+}
+
+/// Lets a handler answer the message it was given, now or later, and talk to
+/// other nodes and services. Cheap to clone and safe to move into a task.
+pub struct HandlerContext<P: maelstrom_protocol::Payload + 'static> {
+    node: Arc<NodeContext>,
+    sender: xtra::WeakAddress<Sender>,
+    rpc: RpcClient<P>,
+    clock: Arc<dyn Clock>,
+    src: String,
+    msg_id: Option<usize>,
+}
+
+impl<P: maelstrom_protocol::Payload + 'static> Clone for HandlerContext<P> {
+    fn clone(&self) -> Self {
+        Self {
+            node: self.node.clone(),
+            sender: self.sender.clone(),
+            rpc: self.rpc.clone(),
+            clock: self.clock.clone(),
+            src: self.src.clone(),
+            msg_id: self.msg_id,
+        }
+    }
+}
+
+impl<P: maelstrom_protocol::Payload + 'static> HandlerContext<P> {
+    pub(crate) fn new(
+        node: Arc<NodeContext>,
+        sender: xtra::WeakAddress<Sender>,
+        rpc: RpcClient<P>,
+        clock: Arc<dyn Clock>,
+        message: &maelstrom_protocol::Message<P>,
+    ) -> Self {
+        Self {
+            node,
+            sender,
+            rpc,
+            clock,
+            src: message.src.clone(),
+            msg_id: message.body.id,
+        }
+    }
+
+    pub fn node(&self) -> &NodeContext {
+        &self.node
+    }
+
+    /// The runtime's clock, for retries and other waits.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Replies to the message this context was created for.
+    pub fn reply(&self, payload: P) {
+        self.reply_with(maelstrom_protocol::Reply::Ok(payload));
+    }
+
+    /// Replies to the message this context was created for with an error.
+    pub fn reply_error(&self, error: impl Into<maelstrom_protocol::Error>) {
+        self.reply_with(maelstrom_protocol::Reply::<P>::Err(error.into()));
+    }
+
+    fn reply_with(&self, reply: maelstrom_protocol::Reply<P>) {
+        let mut message =
+            maelstrom_protocol::Message::new(self.node.node_id.clone(), self.src.clone(), reply);
+        message.body.in_reply_to = self.msg_id;
+
+        // the writer only goes away once the node is shutting down
+        let _ = self.sender.do_send(Output(message));
+    }
+
+    /// Sends a message to any node or client.
+    pub fn send(&self, dest: impl Into<String>, payload: P) {
+        let message =
+            maelstrom_protocol::Message::new(self.node.node_id.clone(), dest.into(), payload);
+
+        // the writer only goes away once the node is shutting down
+        let _ = self.sender.do_send(Output(message));
+    }
+
+    /// Sends a request to another node and waits for its reply.
+    pub async fn rpc(
+        &self,
+        dest: impl Into<String>,
+        payload: P,
+    ) -> Result<maelstrom_protocol::Message<P>, maelstrom_protocol::Error> {
+        self.rpc.rpc(dest, payload).await
+    }
+
+    /// A client for requests to other nodes that outlives this context.
+    pub fn rpc_client(&self) -> RpcClient<P> {
+        self.rpc.clone()
+    }
+
+    /// Sends a request to a service and waits for its reply.
+    pub async fn request<Q: maelstrom_protocol::Payload + 'static>(
+        &self,
+        service: &xtra::WeakAddress<service::Service<Q>>,
+        payload: Q,
+    ) -> Result<maelstrom_protocol::Message<Q>, maelstrom_protocol::Error> {
+        rpc::call(
+            service,
+            service::Request(self.node.node_id.clone(), payload),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_are_named_c_and_a_number() {
+        assert!(is_client("c1"));
+        assert!(is_client("c42"));
+        assert!(!is_client("c"));
+        assert!(!is_client("n1"));
+        assert!(!is_client("cx"));
+        assert!(!is_client("seq-kv"));
+    }
+}
@@ -0,0 +1,68 @@
+use super::service::{Call, Service};
+use crate::maelstrom_protocol;
+use tokio::sync;
+
+/// The service that correlates requests between nodes with their replies.
+pub type Peers<P> = Service<P>;
+
+/// Sends requests to other nodes and waits for the matching reply.
+pub struct RpcClient<P: maelstrom_protocol::Payload + 'static> {
+    node_id: String,
+    peers: xtra::WeakAddress<Peers<P>>,
+}
+
+impl<P: maelstrom_protocol::Payload + 'static> Clone for RpcClient<P> {
+    fn clone(&self) -> Self {
+        Self {
+            node_id: self.node_id.clone(),
+            peers: self.peers.clone(),
+        }
+    }
+}
+
+impl<P: maelstrom_protocol::Payload + 'static> RpcClient<P> {
+    pub fn new(node_id: String, peers: xtra::WeakAddress<Peers<P>>) -> Self {
+        Self { node_id, peers }
+    }
+
+    /// Sends `payload` to `dest` and resolves with its reply.
+    pub async fn rpc(
+        &self,
+        dest: impl Into<String>,
+        payload: P,
+    ) -> Result<maelstrom_protocol::Message<P>, maelstrom_protocol::Error> {
+        call(
+            &self.peers,
+            Call(self.node_id.clone(), dest.into(), payload),
+        )
+        .await
+    }
+}
+
+/// Sends a request through `service` and turns the reply into a `Result`.
+pub(crate) async fn call<Q, M>(
+    service: &xtra::WeakAddress<Service<Q>>,
+    request: M,
+) -> Result<maelstrom_protocol::Message<Q>, maelstrom_protocol::Error>
+where
+    Q: maelstrom_protocol::Payload + 'static,
+    M: xtra::Message<
+        Result = Result<
+            sync::oneshot::Receiver<maelstrom_protocol::Message<maelstrom_protocol::Reply<Q>>>,
+            xtra::Disconnected,
+        >,
+    >,
+    Service<Q>: xtra::Handler<M>,
+{
+    let unavailable = || {
+        maelstrom_protocol::Error::new(maelstrom_protocol::ErrorCode::Crash, "rpc is unavailable")
+    };
+
+    let rx = service
+        .send(request)
+        .await
+        .map_err(|_| unavailable())?
+        .map_err(|_| unavailable())?;
+
+    rx.await.map_err(|_| unavailable())?.into_result()
+}
@@ -0,0 +1,925 @@
+use super::{
+    fault::FaultControl, is_client, service, Clock, Error, HandlerContext, Input, NodeContext,
+    Output, Peers, RpcClient, Rule, Sender, SetFaults, Stdio, TokioClock, Transport, FAULTS_ENV,
+    FAULT_CONTROL_ENV, FAULT_SEED_ENV,
+};
+use crate::{maelstrom_protocol, trace};
+use serde::Deserialize;
+use std::{
+    marker::PhantomData,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader, Lines},
+    sync::mpsc,
+    task,
+};
+use xtra::Actor;
+
+/// The actors a node runs next to: the writer and the services it talks to.
+pub struct Runtime {
+    sender: xtra::Address<Sender>,
+    sender_stopped: task::JoinHandle<()>,
+    services: Mutex<service::Services>,
+    errors: mpsc::UnboundedReceiver<Error>,
+    errors_tx: mpsc::UnboundedSender<Error>,
+    input: Lines<BufReader<Reader>>,
+    clock: Arc<dyn Clock>,
+    tracer: Option<trace::Tracer>,
+    trace_written: Option<task::JoinHandle<()>>,
+    fault_control: bool,
+}
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+impl Runtime {
+    /// Configures a node talking over stdio.
+    pub fn builder() -> RuntimeBuilder {
+        Self::with_transport(Stdio)
+    }
+
+    /// Like [`Runtime::builder`], but talks over the given transport.
+    pub fn with_transport(transport: impl Transport) -> RuntimeBuilder {
+        let (reader, writer) = transport.split();
+
+        RuntimeBuilder {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            clock: Arc::new(TokioClock),
+            trace: std::env::var_os(trace::TRACE_DIR_ENV).map(PathBuf::from),
+            faults: faults_from_env(),
+            fault_seed: fault_seed_from_env(),
+            fault_control: std::env::var_os(FAULT_CONTROL_ENV).is_some(),
+            services: Vec::new(),
+        }
+    }
+
+    /// Spawns the writer. Services are spawned as they are added or first
+    /// asked for.
+    fn start(
+        reader: Reader,
+        writer: Writer,
+        clock: Arc<dyn Clock>,
+        trace_dir: Option<PathBuf>,
+        faults: Vec<Rule>,
+        fault_seed: u64,
+        fault_control: bool,
+    ) -> Self {
+        if !faults.is_empty() || fault_control {
+            eprintln!("drawing faults with seed {fault_seed}");
+        }
+        let (tracer, trace_written) = trace_dir.map(trace::Tracer::spawn).unzip();
+
+        let (errors_tx, errors) = mpsc::unbounded_channel();
+        let mut sender = Sender::from_writer(writer)
+            .with_errors(errors_tx.clone())
+            .with_clock(clock.clone())
+            .with_faults(faults, fault_seed);
+        if let Some(tracer) = &tracer {
+            sender = sender.with_tracer(tracer.clone());
+        }
+        let (sender, manager) = sender.create(None).run();
+        let sender_stopped = tokio::spawn(manager);
+
+        Self {
+            sender,
+            sender_stopped,
+            services: Mutex::default(),
+            errors,
+            errors_tx,
+            input: BufReader::new(reader).lines(),
+            clock,
+            tracer,
+            trace_written,
+            fault_control,
+        }
+    }
+
+    pub fn sender(&self) -> xtra::WeakAddress<Sender> {
+        self.sender.downgrade()
+    }
+
+    pub fn seq_kv(&self) -> xtra::WeakAddress<service::SeqKv> {
+        self.built_in(service::SeqKv::new)
+    }
+
+    pub fn lin_kv(&self) -> xtra::WeakAddress<service::LinKv> {
+        self.built_in(service::LinKv::new)
+    }
+
+    pub fn lww_kv(&self) -> xtra::WeakAddress<service::LwwKv> {
+        self.built_in(service::LwwKv::new)
+    }
+
+    pub fn lin_tso(&self) -> xtra::WeakAddress<service::LinTso> {
+        self.built_in(service::LinTso::new)
+    }
+
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// A service registered with [`RuntimeBuilder::service`], by its name.
+    /// `None` if there is none or it speaks another payload.
+    pub fn service<P: maelstrom_protocol::Payload + 'static>(
+        &self,
+        name: &str,
+    ) -> Option<xtra::WeakAddress<service::Service<P>>> {
+        self.services().get(name)
+    }
+
+    /// The service `make_service` creates, spawned the first time it is asked
+    /// for unless one was registered under its name already.
+    fn built_in<P: maelstrom_protocol::Payload + 'static>(
+        &self,
+        make_service: fn(xtra::WeakAddress<Sender>) -> service::Service<P>,
+    ) -> xtra::WeakAddress<service::Service<P>> {
+        let service = make_service(self.sender()).with_clock(self.clock());
+        let mut services = self.services();
+        if !services.contains(service.name) {
+            return services.spawn(service).downgrade();
+        }
+
+        services.get(service.name).unwrap_or_else(|| {
+            panic!(
+                "service {} is registered with another payload",
+                service.name
+            )
+        })
+    }
+
+    fn services(&self) -> std::sync::MutexGuard<'_, service::Services> {
+        self.services.lock().expect("services lock poisoned")
+    }
+
+    /// Reads the next line, reporting pipeline errors as they come in. Returns
+    /// `None` once the input is closed or the node can no longer write.
+    async fn next_line(&mut self) -> Option<String> {
+        loop {
+            tokio::select! {
+                line = self.input.next_line() => match line {
+                    Ok(line) => {
+                        if let (Some(tracer), Some(line)) = (&self.tracer, &line) {
+                            tracer.record(trace::Direction::In, line.as_bytes());
+                        }
+                        return line;
+                    }
+                    Err(e) => {
+                        eprintln!("failed to read input: {e}");
+                        return None;
+                    }
+                },
+                Some(error) = self.errors.recv() => {
+                    eprintln!("{error}");
+                    if error.is_fatal() {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reports a failure outside the writer along with the writer's own.
+    fn report(&self, error: Error) {
+        let _ = self.errors_tx.send(error);
+    }
+
+    /// Waits for everything already handed to the writer to be written out,
+    /// then prints the errors no one read yet.
+    async fn shutdown(self) {
+        let Self {
+            sender,
+            sender_stopped,
+            mut errors,
+            tracer,
+            trace_written,
+            ..
+        } = self;
+
+        drop(sender);
+        let _ = sender_stopped.await;
+        while let Ok(error) = errors.try_recv() {
+            eprintln!("{error}");
+        }
+
+        drop(tracer);
+        if let Some(trace_written) = trace_written {
+            let _ = trace_written.await;
+        }
+    }
+}
+
+pub struct RuntimeBuilder {
+    reader: Reader,
+    writer: Writer,
+    clock: Arc<dyn Clock>,
+    trace: Option<PathBuf>,
+    faults: Vec<Rule>,
+    fault_seed: u64,
+    fault_control: bool,
+    services: Vec<AddService>,
+}
+
+type AddService = Box<dyn FnOnce(&Runtime) + Send>;
+
+impl RuntimeBuilder {
+    /// Sets the clock used for periodic tasks and request timeouts.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Writes a trace of the node's IO to `dir`, or turns tracing off with
+    /// `None`. Defaults to the directory in [`trace::TRACE_DIR_ENV`].
+    pub fn trace(mut self, dir: Option<PathBuf>) -> Self {
+        self.trace = dir;
+        self
+    }
+
+    /// Sets the fault rules applied to outgoing messages. Defaults to the rules
+    /// in [`FAULTS_ENV`].
+    pub fn faults(mut self, rules: Vec<Rule>) -> Self {
+        self.faults = rules;
+        self
+    }
+
+    /// Sets the seed faults are drawn with. Defaults to the seed in
+    /// [`FAULT_SEED_ENV`], or one taken from the system time.
+    pub fn fault_seed(mut self, seed: u64) -> Self {
+        self.fault_seed = seed;
+        self
+    }
+
+    /// Lets clients switch the fault rules with `set_faults` requests, which
+    /// the runtime answers itself. Defaults to whether [`FAULT_CONTROL_ENV`]
+    /// is set.
+    pub fn fault_control(mut self, enabled: bool) -> Self {
+        self.fault_control = enabled;
+        self
+    }
+
+    /// Adds a service and routes the replies from its name back to it. A
+    /// service named like a built-in one replaces it.
+    ///
+    /// Starting the node panics if two services share a name.
+    pub fn service<P: maelstrom_protocol::Payload + 'static>(
+        mut self,
+        make_service: impl FnOnce(xtra::WeakAddress<Sender>) -> service::Service<P> + Send + 'static,
+    ) -> Self {
+        self.services.push(Box::new(move |runtime| {
+            let service = make_service(runtime.sender()).with_clock(runtime.clock());
+            runtime.services().spawn(service);
+        }));
+        self
+    }
+
+    /// Starts the writer and services, and sets the node actor, created once
+    /// the `init` handshake is done.
+    pub fn node<A, P, F>(self, make_node: F) -> NodeBuilder<A, P, F>
+    where
+        A: xtra::Actor + xtra::Handler<Input<P>>,
+        P: maelstrom_protocol::Payload + 'static,
+        F: FnOnce(NodeContext, &Runtime) -> A,
+    {
+        let runtime = Runtime::start(
+            self.reader,
+            self.writer,
+            self.clock,
+            self.trace,
+            self.faults,
+            self.fault_seed,
+            self.fault_control,
+        );
+        for add_service in self.services {
+            add_service(&runtime);
+        }
+
+        NodeBuilder {
+            runtime,
+            make_node,
+            tasks: Vec::new(),
+            _payload: PhantomData,
+        }
+    }
+}
+
+type Task<A> = Box<dyn FnOnce(xtra::WeakAddress<A>) + Send>;
+
+pub struct NodeBuilder<A: xtra::Actor, P, F> {
+    runtime: Runtime,
+    make_node: F,
+    tasks: Vec<Task<A>>,
+    _payload: PhantomData<fn(P)>,
+}
+
+impl<A, P, F> NodeBuilder<A, P, F>
+where
+    A: xtra::Actor + xtra::Handler<Input<P>>,
+    P: maelstrom_protocol::Payload + 'static,
+    F: FnOnce(NodeContext, &Runtime) -> A,
+{
+    /// Sends the node a fresh `M` every `period` while it is running.
+    pub fn every<M, G>(self, period: Duration, make_message: G) -> Self
+    where
+        A: xtra::Handler<M>,
+        M: xtra::Message<Result = ()>,
+        G: Fn() -> M + Send + 'static,
+    {
+        let clock = self.runtime.clock();
+
+        self.on_start(move |node| {
+            tokio::spawn(async move {
+                loop {
+                    clock.sleep(period).await;
+                    if node.send(make_message()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        })
+    }
+
+    /// Calls `f` with the node's address once the node has been spawned.
+    pub fn on_start<G>(mut self, f: G) -> Self
+    where
+        G: FnOnce(xtra::WeakAddress<A>) + Send + 'static,
+    {
+        self.tasks.push(Box::new(f));
+        self
+    }
+
+    /// Runs the node until its input is closed.
+    pub async fn run(self) {
+        let Self {
+            runtime,
+            make_node,
+            tasks,
+            ..
+        } = self;
+
+        run_io(runtime, |context, runtime| {
+            let (node, manager) = make_node(context, runtime).create(None).run();
+            let stopped = tokio::spawn(manager);
+
+            for task in tasks {
+                task(node.downgrade());
+            }
+
+            (node, stopped)
+        })
+        .await;
+    }
+}
+
+async fn run_io<A, P, F>(mut runtime: Runtime, spawn_node: F)
+where
+    A: xtra::Actor + xtra::Handler<Input<P>>,
+    P: maelstrom_protocol::Payload + 'static,
+    F: FnOnce(NodeContext, &Runtime) -> (xtra::Address<A>, task::JoinHandle<()>),
+{
+    let sender = runtime.sender.clone();
+
+    // init handshake
+    let mut context = None;
+    while let Some(line) = runtime.next_line().await {
+        let message = match serde_json::from_str::<
+            maelstrom_protocol::Message<maelstrom_protocol::Handshake>,
+        >(&line)
+        {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("ignoring message before init: {e}: {line}");
+                continue;
+            }
+        };
+
+        if let maelstrom_protocol::Handshake::Init(init) = &message.body.payload {
+            let init_ok = message.make_response(maelstrom_protocol::Handshake::InitOk);
+            match sender.do_send(Output(init_ok)) {
+                Ok(()) => context = Some(NodeContext::from(init.clone())),
+                Err(xtra::Disconnected) => runtime.report(Error::WriterStopped),
+            }
+            break;
+        }
+    }
+
+    let Some(context) = context else {
+        drop(sender);
+        runtime.shutdown().await;
+        return;
+    };
+
+    let context = Arc::new(context);
+    let peers = Peers::<P>::named("peers", sender.downgrade())
+        .with_clock(runtime.clock())
+        .create(None)
+        .spawn(&mut xtra::spawn::Tokio::Global);
+    let rpc = RpcClient::new(context.node_id.clone(), peers.downgrade());
+    let (node, node_stopped) = spawn_node(context.as_ref().clone(), &runtime);
+
+    while let Some(line) = runtime.next_line().await {
+        let envelope = match serde_json::from_str::<maelstrom_protocol::Envelope>(&line) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                eprintln!("malformed message: {e}: {line}");
+                reply_malformed(&sender, &line, e.to_string());
+                continue;
+            }
+        };
+
+        // services
+        let routed = runtime.services().route(envelope);
+        let envelope = match routed {
+            Ok(routed) => {
+                if let Err(e) = routed {
+                    eprintln!("malformed service reply: {e}: {line}");
+                }
+                continue;
+            }
+            Err(envelope) => envelope,
+        };
+
+        // the body is parsed once; the header is only read to answer errors
+        let maelstrom_protocol::Envelope {
+            src,
+            dst,
+            body: raw,
+        } = envelope;
+        let reply_error = |error: maelstrom_protocol::Error| {
+            if let Some(reply) = error_reply(&line, error) {
+                let _ = sender.do_send(Output(reply));
+            }
+        };
+
+        let body =
+            match runtime.fault_control && is_client(&src) {
+                true => serde_json::from_str::<maelstrom_protocol::Body<Inbound<P>>>(raw.get()),
+                false => serde_json::from_str::<
+                    maelstrom_protocol::Body<maelstrom_protocol::Reply<P>>,
+                >(raw.get())
+                .map(|body| maelstrom_protocol::Body {
+                    id: body.id,
+                    in_reply_to: body.in_reply_to,
+                    payload: Inbound::Node(body.payload),
+                }),
+            };
+        let body = match body {
+            Ok(body) => body,
+            Err(_) => {
+                // the untagged parse only says nothing matched, so ask the payload why
+                let e = serde_json::from_str::<maelstrom_protocol::Body<P>>(raw.get()).err();
+                let kind = serde_json::from_str::<maelstrom_protocol::Header>(raw.get())
+                    .ok()
+                    .and_then(|header| header.kind);
+                match (e, kind) {
+                    (Some(e), Some(kind)) if is_unknown_type(&e, &kind) => {
+                        reply_error(maelstrom_protocol::Error::new(
+                            maelstrom_protocol::ErrorCode::NotSupported,
+                            format!("message type {kind} is not supported"),
+                        ));
+                    }
+                    (e, _) => {
+                        let e =
+                            e.map_or_else(|| "unexpected message".to_string(), |e| e.to_string());
+                        eprintln!("malformed message: {e}: {line}");
+                        reply_malformed(&sender, &line, e);
+                    }
+                }
+                continue;
+            }
+        };
+        let (id, in_reply_to) = (body.id, body.in_reply_to);
+
+        let message = match (body.payload, in_reply_to) {
+            (Inbound::Control(control), None) => {
+                set_faults(&sender, with_body(&src, &dst, id, in_reply_to, control));
+                continue;
+            }
+            (Inbound::Control(_), Some(_)) => continue,
+            // replies to node-to-node requests
+            (Inbound::Node(payload), Some(_)) => {
+                let Ok(Some(message)) = peers
+                    .send(service::Response(with_body(
+                        &src,
+                        &dst,
+                        id,
+                        in_reply_to,
+                        payload,
+                    )))
+                    .await
+                else {
+                    continue;
+                };
+                let Ok(message) = message.into_result() else {
+                    continue;
+                };
+                message
+            }
+            (Inbound::Node(maelstrom_protocol::Reply::Ok(payload)), None) => {
+                with_body(&src, &dst, id, in_reply_to, payload)
+            }
+            (Inbound::Node(maelstrom_protocol::Reply::Err(error)), None) => {
+                eprintln!("unexpected error from {src}: {error}");
+                continue;
+            }
+        };
+
+        let cx = HandlerContext::new(
+            context.clone(),
+            sender.downgrade(),
+            rpc.clone(),
+            runtime.clock(),
+            &message,
+        );
+        if node.do_send(Input(message, cx)).is_err() {
+            eprintln!("node stopped, shutting down");
+            break;
+        }
+    }
+
+    // let the node finish what it has queued, then flush what it sent
+    drop(node);
+    let _ = node_stopped.await;
+    drop(sender);
+    runtime.shutdown().await;
+}
+
+fn faults_from_env() -> Vec<Rule> {
+    let Some(spec) = std::env::var_os(FAULTS_ENV) else {
+        return Vec::new();
+    };
+
+    match Rule::parse_all(&spec.to_string_lossy()) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("ignoring {FAULTS_ENV}: {e}");
+            Vec::new()
+        }
+    }
+}
+
+fn fault_seed_from_env() -> u64 {
+    let seed = std::env::var(FAULT_SEED_ENV)
+        .ok()
+        .and_then(|seed| match seed.parse() {
+            Ok(seed) => Some(seed),
+            Err(e) => {
+                eprintln!("ignoring {FAULT_SEED_ENV}: {e}");
+                None
+            }
+        });
+
+    seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64
+    })
+}
+
+/// Answers the request on `line` with `error`. The request is read as
+/// leniently as possible, so that even a malformed one can be answered.
+fn error_reply(
+    line: &str,
+    error: maelstrom_protocol::Error,
+) -> Option<maelstrom_protocol::Message<maelstrom_protocol::Error>> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    let body = value.get("body")?;
+    let header = maelstrom_protocol::Header {
+        kind: body
+            .get("type")
+            .and_then(|kind| kind.as_str())
+            .map(String::from),
+        id: body
+            .get("msg_id")
+            .and_then(|id| id.as_u64())
+            .map(|id| id as usize),
+        in_reply_to: None,
+    };
+    if !header.is_request() || body.get("in_reply_to").is_some_and(|id| !id.is_null()) {
+        return None;
+    }
+
+    let mut reply = maelstrom_protocol::Message::new(
+        value.get("dest")?.as_str()?.to_string(),
+        value.get("src")?.as_str()?.to_string(),
+        error,
+    );
+    reply.body.in_reply_to = header.id;
+    Some(reply)
+}
+
+/// Answers a request that could not be parsed, if it can be told who sent it.
+fn reply_malformed(sender: &xtra::Address<Sender>, line: &str, text: String) {
+    let error =
+        maelstrom_protocol::Error::new(maelstrom_protocol::ErrorCode::MalformedRequest, text);
+    if let Some(reply) = error_reply(line, error) {
+        let _ = sender.do_send(Output(reply));
+    }
+}
+
+fn with_body<Q>(
+    src: &str,
+    dst: &str,
+    id: Option<usize>,
+    in_reply_to: Option<usize>,
+    payload: Q,
+) -> maelstrom_protocol::Message<Q> {
+    maelstrom_protocol::Message {
+        src: src.to_string(),
+        dst: dst.to_string(),
+        body: maelstrom_protocol::Body {
+            id,
+            in_reply_to,
+            payload,
+        },
+    }
+}
+
+/// Whether `error`, from parsing a body of type `kind`, says the payload has
+/// no variant for that type, rather than that the body does not fit one.
+fn is_unknown_type(error: &serde_json::Error, kind: &str) -> bool {
+    error
+        .to_string()
+        .starts_with(&format!("unknown variant `{kind}`"))
+}
+
+/// What a message body that is not a service reply can be. Only clients get
+/// to send `set_faults`, and only when fault control is on.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Inbound<P> {
+    Control(FaultControl),
+    Node(maelstrom_protocol::Reply<P>),
+}
+
+/// Switches the writer to the fault rules in a `set_faults` request.
+fn set_faults(sender: &xtra::Address<Sender>, message: maelstrom_protocol::Message<FaultControl>) {
+    let FaultControl::SetFaults { rules } = &message.body.payload else {
+        return;
+    };
+
+    let reply = match Rule::parse_all(rules) {
+        Ok(rules) => {
+            let _ = sender.do_send(SetFaults(rules));
+            message.make_response(FaultControl::SetFaultsOk)
+        }
+        Err(e) => message.make_error_response(maelstrom_protocol::Error::new(
+            maelstrom_protocol::ErrorCode::MalformedRequest,
+            e.to_string(),
+        )),
+    };
+    let _ = sender.do_send(Output(reply));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::ManualClock;
+    use serde::Serialize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Payload {
+        Echo { echo: String },
+    }
+
+    impl maelstrom_protocol::Payload for Payload {}
+
+    struct Idle;
+
+    impl xtra::Actor for Idle {}
+
+    #[async_trait::async_trait]
+    impl xtra::Handler<Input<Payload>> for Idle {
+        async fn handle(&mut self, _: Input<Payload>, _ctx: &mut xtra::Context<Self>) {}
+    }
+
+    fn start(builder: RuntimeBuilder) -> Runtime {
+        builder
+            .trace(None)
+            .faults(Vec::new())
+            .node(|_, _| Idle)
+            .runtime
+    }
+
+    #[tokio::test]
+    async fn built_in_services_start_when_asked_for() {
+        let (transport, _) = tokio::io::duplex(64);
+        let runtime = start(Runtime::with_transport(transport));
+        assert!(!runtime.services().contains("seq-kv"));
+
+        runtime.seq_kv();
+        runtime.seq_kv();
+        assert!(runtime.services().contains("seq-kv"));
+        assert!(runtime.service::<service::SeqKvPayload>("seq-kv").is_some());
+        assert!(!runtime.services().contains("lin-kv"));
+    }
+
+    #[tokio::test]
+    async fn services_are_found_by_name() {
+        let (transport, _) = tokio::io::duplex(64);
+        let runtime = start(
+            Runtime::with_transport(transport)
+                .service(|sender| service::Service::<service::SeqKvPayload>::named("a", sender))
+                .service(|sender| service::Service::<service::SeqKvPayload>::named("b", sender))
+                .service(service::LinTso::new),
+        );
+
+        assert!(runtime.service::<service::SeqKvPayload>("a").is_some());
+        assert!(runtime.service::<service::SeqKvPayload>("b").is_some());
+        assert!(runtime.service::<service::LinKvPayload>("a").is_none());
+        assert!(runtime
+            .service::<service::LinTsoPayload>("lin-tso")
+            .is_some());
+        assert!(runtime.service::<service::SeqKvPayload>("seq-kv").is_none());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "service seq-kv is registered with another payload")]
+    async fn built_in_name_with_another_payload_panics() {
+        let (transport, _) = tokio::io::duplex(64);
+        let runtime = start(
+            Runtime::with_transport(transport)
+                .service(|sender| service::LinKv::named("seq-kv", sender)),
+        );
+
+        runtime.seq_kv();
+    }
+
+    /// Runs an idle node, feeds it `init` and then `lines`, and returns the
+    /// type of each reply after `init_ok`, with the code of errors.
+    async fn replies(fault_control: bool, lines: &[&str]) -> Vec<String> {
+        let (node_end, kit_end) = tokio::io::duplex(1 << 16);
+        let (output, mut input) = tokio::io::split(kit_end);
+        let node = tokio::spawn(
+            Runtime::with_transport(node_end)
+                .trace(None)
+                .faults(Vec::new())
+                .fault_control(fault_control)
+                .node(|_, _| Idle)
+                .run(),
+        );
+
+        let init = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":0,"node_id":"n1","node_ids":["n1","n2"]}}"#;
+        for line in std::iter::once(&init).chain(lines) {
+            tokio::io::AsyncWriteExt::write_all(&mut input, format!("{line}\n").as_bytes())
+                .await
+                .unwrap();
+        }
+        tokio::io::AsyncWriteExt::shutdown(&mut input)
+            .await
+            .unwrap();
+        node.await.unwrap();
+
+        let mut output = BufReader::new(output).lines();
+        let mut kinds = Vec::new();
+        while let Some(line) = output.next_line().await.unwrap() {
+            let reply: serde_json::Value = serde_json::from_str(&line).unwrap();
+            kinds.push(match reply["body"]["type"].as_str().unwrap() {
+                "error" => format!("error {}", reply["body"]["code"]),
+                kind => kind.to_string(),
+            });
+        }
+        assert_eq!(kinds.remove(0), "init_ok");
+        kinds
+    }
+
+    /// Counts the ticks it is sent.
+    struct Ticks(Arc<AtomicUsize>);
+
+    impl xtra::Actor for Ticks {}
+
+    struct Tick;
+
+    impl xtra::Message for Tick {
+        type Result = ();
+    }
+
+    #[async_trait::async_trait]
+    impl xtra::Handler<Tick> for Ticks {
+        async fn handle(&mut self, _: Tick, _ctx: &mut xtra::Context<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl xtra::Handler<Input<Payload>> for Ticks {
+        async fn handle(&mut self, _: Input<Payload>, _ctx: &mut xtra::Context<Self>) {}
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn every_ticks_as_a_manual_clock_is_advanced() {
+        let clock = Arc::new(ManualClock::new());
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let (node_end, mut kit_end) = tokio::io::duplex(1 << 16);
+
+        let counted = ticks.clone();
+        let node = tokio::spawn(
+            Runtime::with_transport(node_end)
+                .clock(clock.clone())
+                .trace(None)
+                .faults(Vec::new())
+                .fault_control(false)
+                .node(|_, _| Ticks(counted))
+                .every(Duration::from_millis(10), || Tick)
+                .run(),
+        );
+        let init = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":0,"node_id":"n1","node_ids":["n1"]}}"#;
+        tokio::io::AsyncWriteExt::write_all(&mut kit_end, format!("{init}\n").as_bytes())
+            .await
+            .unwrap();
+
+        // the paused tokio clock moves on only once every task is waiting
+        let ticks_after = |advance| {
+            clock.advance(advance);
+            let ticks = ticks.clone();
+            async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                ticks.load(Ordering::SeqCst)
+            }
+        };
+        assert_eq!(ticks_after(Duration::ZERO).await, 0);
+        assert_eq!(ticks_after(Duration::from_millis(9)).await, 0);
+        assert_eq!(ticks_after(Duration::from_millis(1)).await, 1);
+        assert_eq!(ticks_after(Duration::from_millis(10)).await, 2);
+        assert_eq!(ticks_after(Duration::from_millis(10)).await, 3);
+
+        drop(kit_end);
+        node.await.unwrap();
+    }
+
+    const SET_FAULTS: &str =
+        r#"{"src":"c1","dest":"n1","body":{"type":"set_faults","msg_id":1,"rules":"drop 0.5"}}"#;
+
+    #[tokio::test]
+    async fn set_faults_is_answered_only_when_enabled() {
+        assert_eq!(replies(true, &[SET_FAULTS]).await, ["set_faults_ok"]);
+        assert_eq!(replies(false, &[SET_FAULTS]).await, ["error 10"]);
+    }
+
+    #[tokio::test]
+    async fn set_faults_is_only_taken_from_clients() {
+        let from_node = SET_FAULTS.replace(r#""src":"c1""#, r#""src":"n2""#);
+        assert_eq!(replies(true, &[&from_node]).await, ["error 10"]);
+    }
+
+    #[tokio::test]
+    async fn unknown_and_malformed_requests_are_told_apart() {
+        let replies = replies(
+            false,
+            &[
+                r#"{"src":"c1","dest":"n1","body":{"type":"nope","msg_id":1}}"#,
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":2}}"#,
+                r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":3,"echo":7}}"#,
+                // replies and messages that expect none are not answered
+                r#"{"src":"c1","dest":"n1","body":{"type":"nope"}}"#,
+                r#"{"src":"c1","dest":"n1","body":{"type":"nope_ok","msg_id":4}}"#,
+            ],
+        )
+        .await;
+        assert_eq!(replies, ["error 10", "error 12", "error 12"]);
+    }
+
+    fn malformed(line: &str) -> Option<serde_json::Value> {
+        let error =
+            maelstrom_protocol::Error::new(maelstrom_protocol::ErrorCode::MalformedRequest, "bad");
+        error_reply(line, error).map(|reply| serde_json::to_value(reply).unwrap())
+    }
+
+    #[test]
+    fn malformed_request_is_answered() {
+        let reply = malformed(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":4}}"#);
+        assert_eq!(
+            reply,
+            Some(serde_json::json!({
+                "src": "n1",
+                "dest": "c1",
+                "body": {"msg_id": null, "in_reply_to": 4, "type": "error", "code": 12, "text": "bad"},
+            }))
+        );
+
+        // a header with a type that is not a string is still answered
+        let reply = malformed(r#"{"src":"c1","dest":"n1","body":{"type":7,"msg_id":4}}"#);
+        assert!(reply.is_some());
+        let reply = malformed(r#"{"src":"c1","dest":"n1","body":{"msg_id":4,"in_reply_to":null}}"#);
+        assert!(reply.is_some());
+    }
+
+    #[test]
+    fn malformed_reply_is_not_answered() {
+        for line in [
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo_ok","msg_id":4}}"#,
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":4,"in_reply_to":"x"}}"#,
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo"}}"#,
+            r#"{"dest":"n1","body":{"type":"echo","msg_id":4}}"#,
+            "not json",
+        ] {
+            assert_eq!(malformed(line), None, "{line}");
+        }
+    }
+}
@@ -1,20 +1,208 @@
-use crate::maelstrom_protocol;
-use tokio::io::{self, AsyncWriteExt, BufWriter, Stdout};
+use super::{
+    fault::{Fault, Faults, Rule, SetFaults},
+    Clock, Error, TokioClock,
+};
+use crate::{maelstrom_protocol, trace};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{self, AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
 
 pub struct Sender {
     id: usize,
-    inner: BufWriter<Stdout>,
+    inner: BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    errors: Option<mpsc::UnboundedSender<Error>>,
+    tracer: Option<trace::Tracer>,
+    clock: Arc<dyn Clock>,
+    faults: Faults,
+    /// Messages held back to be written after the next one.
+    held: Vec<Vec<u8>>,
+    /// Messages written since the last flush.
+    unflushed: usize,
+    /// When the oldest unflushed message was written.
+    batch_started: Option<Instant>,
+    /// Whether a [`Flush`] is waiting behind the queued messages.
+    flush_queued: bool,
 }
 
-impl xtra::Actor for Sender {}
+/// How long a reordered message waits for another one to overtake it.
+const REORDER_WINDOW: Duration = Duration::from_millis(100);
+
+/// Most messages written between flushes.
+const MAX_BATCH: usize = 64;
+
+/// Longest a message waits in the buffer while its batch keeps growing.
+const MAX_BATCH_DELAY: Duration = Duration::from_millis(5);
+
+#[async_trait::async_trait]
+impl xtra::Actor for Sender {
+    async fn stopped(&mut self) {
+        if let Err(error) = self.flush().await {
+            self.report(error);
+        }
+    }
+}
+
+impl Default for Sender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Sender {
     pub fn new() -> Self {
+        Self::from_writer(io::stdout())
+    }
+
+    pub fn from_writer(writer: impl AsyncWrite + Send + Unpin + 'static) -> Self {
         Self {
             id: 0,
-            inner: BufWriter::new(io::stdout()),
+            inner: BufWriter::new(Box::new(writer)),
+            errors: None,
+            tracer: None,
+            clock: Arc::new(TokioClock),
+            faults: Faults::default(),
+            held: Vec::new(),
+            unflushed: 0,
+            batch_started: None,
+            flush_queued: false,
+        }
+    }
+
+    /// Reports write failures on `errors` instead of stderr.
+    pub fn with_errors(mut self, errors: mpsc::UnboundedSender<Error>) -> Self {
+        self.errors.replace(errors);
+        self
+    }
+
+    /// Records every line written to the trace.
+    pub(crate) fn with_tracer(mut self, tracer: trace::Tracer) -> Self {
+        self.tracer.replace(tracer);
+        self
+    }
+
+    /// Sets the clock that times batches and delayed messages.
+    pub(crate) fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Applies fault rules to everything written, drawing faults from `seed`.
+    pub(crate) fn with_faults(mut self, rules: Vec<Rule>, seed: u64) -> Self {
+        self.faults = Faults::new(rules, seed);
+        self
+    }
+
+    /// Writes a message unless a fault rule gets in the way.
+    async fn send(
+        &mut self,
+        src: &str,
+        dst: &str,
+        buf: Vec<u8>,
+        ctx: &mut xtra::Context<Self>,
+    ) -> Result<(), Error> {
+        match self.faults.decide(src, dst, &buf) {
+            Fault::None => self.write(&buf).await?,
+            Fault::Drop => return Ok(()),
+            Fault::Duplicate => {
+                self.write(&buf).await?;
+                self.write(&buf).await?;
+            }
+            Fault::Delay(delay) => {
+                self.release_after(delay, Some(buf), ctx);
+                return Ok(());
+            }
+            Fault::Reorder => {
+                self.held.push(buf);
+                self.release_after(REORDER_WINDOW, None, ctx);
+                return Ok(());
+            }
+        }
+
+        self.write_held().await
+    }
+
+    /// Hands `buf` back to the writer after `delay`, along with anything held.
+    fn release_after(&self, delay: Duration, buf: Option<Vec<u8>>, ctx: &mut xtra::Context<Self>) {
+        // the writer is stopping, so whatever was delayed is lost
+        let Ok(address) = ctx.address() else {
+            return;
+        };
+
+        let clock = self.clock.clone();
+        tokio::spawn(async move {
+            clock.sleep(delay).await;
+            let _ = address.send(Release(buf)).await;
+        });
+    }
+
+    async fn release(&mut self, buf: Option<Vec<u8>>) -> Result<(), Error> {
+        if let Some(buf) = buf {
+            self.write(&buf).await?;
+        }
+        self.write_held().await
+    }
+
+    async fn write_held(&mut self) -> Result<(), Error> {
+        for buf in std::mem::take(&mut self.held) {
+            self.write(&buf).await?;
+        }
+        Ok(())
+    }
+
+    /// Buffers a line, flushing once the batch is full or has waited long
+    /// enough.
+    async fn write(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.inner.write_all(buf).await?;
+        self.inner.write_all(b"\n").await?;
+
+        if let Some(tracer) = &self.tracer {
+            tracer.record(trace::Direction::Out, buf);
+        }
+
+        self.unflushed += 1;
+        let batch_started = *self.batch_started.get_or_insert_with(|| self.clock.now());
+        if self.unflushed >= MAX_BATCH || self.clock.now() - batch_started >= MAX_BATCH_DELAY {
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the batch once the messages queued so far are written.
+    fn end_batch(&mut self, ctx: &mut xtra::Context<Self>) {
+        if self.unflushed == 0 || self.flush_queued {
+            return;
+        }
+
+        // when stopping, the batch is flushed once the queue is done instead
+        if let Ok(address) = ctx.address() {
+            self.flush_queued = address.do_send(Flush).is_ok();
         }
     }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        self.unflushed = 0;
+        self.batch_started = None;
+        self.inner.flush().await?;
+        Ok(())
+    }
+
+    fn report(&self, error: Error) {
+        let error = match &self.errors {
+            Some(errors) => match errors.send(error) {
+                Ok(()) => return,
+                Err(mpsc::error::SendError(error)) => error,
+            },
+            None => error,
+        };
+
+        eprintln!("{error}");
+    }
 }
 
 pub struct Output<P>(pub maelstrom_protocol::Message<P>);
@@ -34,24 +222,201 @@ where
     async fn handle(
         &mut self,
         Output(mut message): Output<P>,
-        _ctx: &mut xtra::Context<Self>,
+        ctx: &mut xtra::Context<Self>,
     ) -> usize {
         let id = self.id;
         message.body.id = Some(id);
         self.id += 1;
 
-        let buf = serde_json::to_vec(&message).expect("failed to serialize message");
+        let written = match serde_json::to_vec(&message) {
+            Ok(buf) => self.send(&message.src, &message.dst, buf, ctx).await,
+            Err(e) => Err(e.into()),
+        };
 
-        self.inner
-            .write_all(&buf)
-            .await
-            .expect("failed to write to stdout");
-        self.inner
-            .write_all(&[b'\n'])
-            .await
-            .expect("failed to write newline to stdout");
-        self.inner.flush().await.expect("failed to flush stdout");
+        if let Err(error) = written {
+            self.report(error);
+        }
+        self.end_batch(ctx);
 
         id
     }
 }
+
+/// A delayed message coming due, or the end of a reorder window.
+struct Release(Option<Vec<u8>>);
+
+impl xtra::Message for Release {
+    type Result = ();
+}
+
+#[async_trait::async_trait]
+impl xtra::Handler<Release> for Sender {
+    async fn handle(&mut self, Release(buf): Release, ctx: &mut xtra::Context<Self>) {
+        if let Err(error) = self.release(buf).await {
+            self.report(error);
+        }
+        self.end_batch(ctx);
+    }
+}
+
+#[async_trait::async_trait]
+impl xtra::Handler<SetFaults> for Sender {
+    async fn handle(&mut self, SetFaults(rules): SetFaults, _ctx: &mut xtra::Context<Self>) {
+        self.faults.set_rules(rules);
+    }
+}
+
+/// Flushes whatever was written since the last flush.
+struct Flush;
+
+impl xtra::Message for Flush {
+    type Result = ();
+}
+
+#[async_trait::async_trait]
+impl xtra::Handler<Flush> for Sender {
+    async fn handle(&mut self, _: Flush, _ctx: &mut xtra::Context<Self>) {
+        self.flush_queued = false;
+        if let Err(error) = self.flush().await {
+            self.report(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::ManualClock;
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader, DuplexStream};
+    use xtra::Actor;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Payload {
+        Note { n: usize },
+        Slow { n: usize },
+        Late { n: usize },
+    }
+
+    impl maelstrom_protocol::Payload for Payload {}
+
+    fn line(n: usize) -> Vec<u8> {
+        format!(r#"{{"src":"n1","dest":"n2","body":{{"type":"note","n":{n}}}}}"#).into_bytes()
+    }
+
+    /// How many lines can be read right now, without waiting for more.
+    fn readable_lines(output: &mut DuplexStream) -> usize {
+        let mut lines = 0;
+        let mut chunk = [0; 1 << 16];
+        while let Some(Ok(read)) = futures::FutureExt::now_or_never(output.read(&mut chunk)) {
+            if read == 0 {
+                break;
+            }
+            lines += chunk[..read].iter().filter(|b| **b == b'\n').count();
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn writes_are_coalesced_up_to_a_full_batch() {
+        let (writer, mut output) = io::duplex(1 << 20);
+        let mut sender = Sender::from_writer(writer).with_clock(Arc::new(ManualClock::new()));
+
+        for n in 0..MAX_BATCH - 1 {
+            sender.write(&line(n)).await.unwrap();
+        }
+        assert_eq!(readable_lines(&mut output), 0);
+
+        sender.write(&line(MAX_BATCH)).await.unwrap();
+        assert_eq!(readable_lines(&mut output), MAX_BATCH);
+
+        sender.write(&line(MAX_BATCH + 1)).await.unwrap();
+        assert_eq!(readable_lines(&mut output), 0);
+    }
+
+    #[tokio::test]
+    async fn a_partial_batch_is_flushed_once_it_has_waited_long_enough() {
+        let (writer, mut output) = io::duplex(1 << 20);
+        let clock = Arc::new(ManualClock::new());
+        let mut sender = Sender::from_writer(writer).with_clock(clock.clone());
+
+        sender.write(&line(0)).await.unwrap();
+        clock.advance(MAX_BATCH_DELAY - Duration::from_millis(1));
+        sender.write(&line(1)).await.unwrap();
+        assert_eq!(readable_lines(&mut output), 0);
+
+        clock.advance(Duration::from_millis(1));
+        sender.write(&line(2)).await.unwrap();
+        assert_eq!(readable_lines(&mut output), 3);
+
+        // the next batch starts with the next write
+        clock.advance(MAX_BATCH_DELAY);
+        sender.write(&line(3)).await.unwrap();
+        assert_eq!(readable_lines(&mut output), 0);
+    }
+
+    async fn read_lines(
+        output: &mut tokio::io::Lines<BufReader<DuplexStream>>,
+        count: usize,
+    ) -> Vec<Value> {
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            let line = tokio::time::timeout(Duration::from_secs(1), output.next_line())
+                .await
+                .expect("sender did not write in time")
+                .unwrap()
+                .expect("sender stopped");
+            lines.push(serde_json::from_str(&line).unwrap());
+        }
+        lines
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn order_and_msg_ids_survive_batches_and_held_messages() {
+        let (writer, output) = io::duplex(1 << 20);
+        let mut output = BufReader::new(output).lines();
+        let clock = Arc::new(ManualClock::new());
+        let rules = Rule::parse_all("delay 10ms type=slow; reorder 1 type=late").unwrap();
+        let (sender, manager) = Sender::from_writer(writer)
+            .with_clock(clock.clone())
+            .with_faults(rules, 0)
+            .create(None)
+            .run();
+        tokio::spawn(manager);
+
+        // more than two batches, queued at once
+        let count = 2 * MAX_BATCH + 10;
+        let (slow, late) = (MAX_BATCH - 1, MAX_BATCH + 5);
+        for n in 0..count {
+            let payload = match n {
+                n if n == slow => Payload::Slow { n },
+                n if n == late => Payload::Late { n },
+                n => Payload::Note { n },
+            };
+            let message = maelstrom_protocol::Message::new("n1".into(), "n2".into(), payload);
+            sender.do_send(Output(message)).unwrap();
+        }
+
+        // the last partial batch is flushed once the queue is done
+        let written = read_lines(&mut output, count - 1).await;
+        let order: Vec<_> = written
+            .iter()
+            .map(|line| line["body"]["n"].as_u64().unwrap() as usize)
+            .collect();
+        let mut expected: Vec<_> = (0..count).filter(|n| *n != slow).collect();
+        // the late message goes out right after the one that followed it
+        let at = expected.iter().position(|n| *n == late).unwrap();
+        expected.swap(at, at + 1);
+        assert_eq!(order, expected);
+        for line in &written {
+            assert_eq!(line["body"]["msg_id"], line["body"]["n"]);
+        }
+
+        clock.advance(Duration::from_millis(10));
+        let released = read_lines(&mut output, 1).await;
+        assert_eq!(released[0]["body"]["type"], "slow");
+        assert_eq!(released[0]["body"]["msg_id"], slow);
+    }
+}
@@ -1,29 +1,109 @@
-use super::{Output, Sender};
+use super::{Clock, Output, Sender, TokioClock};
 use crate::maelstrom_protocol;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync;
 
-mod seq_kv;
+mod kv;
+mod lin_tso;
+mod registry;
 
-pub use seq_kv::*;
+pub use kv::*;
+pub use lin_tso::*;
+pub use registry::*;
+
+/// How long a request waits for its reply unless configured otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// How often expired and abandoned requests are cleaned up.
+const EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
 
 pub struct Service<P> {
     pub name: &'static str,
     sender: xtra::WeakAddress<Sender>,
-    pending_request: HashMap<usize, sync::oneshot::Sender<maelstrom_protocol::Message<P>>>,
+    timeout: Duration,
+    clock: Arc<dyn Clock>,
+    pending_request: HashMap<usize, PendingRequest<P>>,
+}
+
+struct PendingRequest<P> {
+    from: String,
+    dest: String,
+    deadline: Instant,
+    tx: sync::oneshot::Sender<maelstrom_protocol::Message<maelstrom_protocol::Reply<P>>>,
+}
+
+#[async_trait::async_trait]
+impl<P: maelstrom_protocol::Payload + 'static> xtra::Actor for Service<P> {
+    async fn started(&mut self, ctx: &mut xtra::Context<Self>) {
+        let service = ctx.address().expect("service is running").downgrade();
+        let clock = self.clock.clone();
+
+        tokio::spawn(async move {
+            loop {
+                clock.sleep(EXPIRE_INTERVAL).await;
+                if service.send(Expire).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
 }
 
-impl<P: maelstrom_protocol::Payload + 'static> xtra::Actor for Service<P> {}
+impl<P> Service<P> {
+    pub fn named(name: &'static str, sender: xtra::WeakAddress<Sender>) -> Self {
+        Self {
+            name,
+            sender,
+            timeout: DEFAULT_TIMEOUT,
+            clock: Arc::new(TokioClock),
+            pending_request: Default::default(),
+        }
+    }
 
+    /// Sets how long each request waits for its reply before timing out.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the clock deadlines are measured against.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+/// A request to the service itself: `(from, payload)`.
 pub struct Request<P>(pub String, pub P);
-pub struct Response<P>(pub maelstrom_protocol::Message<P>);
+/// A request to an arbitrary destination: `(from, dest, payload)`.
+pub struct Call<P>(pub String, pub String, pub P);
+pub struct Response<P>(pub maelstrom_protocol::Message<maelstrom_protocol::Reply<P>>);
+struct Expire;
 
 impl<P: maelstrom_protocol::Payload + 'static> xtra::Message for Request<P> {
-    type Result =
-        Result<sync::oneshot::Receiver<maelstrom_protocol::Message<P>>, xtra::Disconnected>;
+    type Result = Result<
+        sync::oneshot::Receiver<maelstrom_protocol::Message<maelstrom_protocol::Reply<P>>>,
+        xtra::Disconnected,
+    >;
+}
+
+impl<P: maelstrom_protocol::Payload + 'static> xtra::Message for Call<P> {
+    type Result = Result<
+        sync::oneshot::Receiver<maelstrom_protocol::Message<maelstrom_protocol::Reply<P>>>,
+        xtra::Disconnected,
+    >;
 }
 
 impl<P: maelstrom_protocol::Payload + 'static> xtra::Message for Response<P> {
+    /// The response, when it did not match a pending request.
+    type Result = Option<maelstrom_protocol::Message<maelstrom_protocol::Reply<P>>>;
+}
+
+impl xtra::Message for Expire {
     type Result = ();
 }
 
@@ -32,13 +112,39 @@ impl<P: maelstrom_protocol::Payload + 'static> xtra::Handler<Request<P>> for Ser
     async fn handle(
         &mut self,
         Request(from, payload): Request<P>,
+        ctx: &mut xtra::Context<Self>,
+    ) -> Result<
+        sync::oneshot::Receiver<maelstrom_protocol::Message<maelstrom_protocol::Reply<P>>>,
+        xtra::Disconnected,
+    > {
+        let dest = self.name.to_string();
+        self.handle(Call(from, dest, payload), ctx).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: maelstrom_protocol::Payload + 'static> xtra::Handler<Call<P>> for Service<P> {
+    async fn handle(
+        &mut self,
+        Call(from, dest, payload): Call<P>,
         _ctx: &mut xtra::Context<Self>,
-    ) -> Result<sync::oneshot::Receiver<maelstrom_protocol::Message<P>>, xtra::Disconnected> {
+    ) -> Result<
+        sync::oneshot::Receiver<maelstrom_protocol::Message<maelstrom_protocol::Reply<P>>>,
+        xtra::Disconnected,
+    > {
         let (tx, rx) = sync::oneshot::channel();
 
-        let message = maelstrom_protocol::Message::new(from, self.name.to_string(), payload);
+        let message = maelstrom_protocol::Message::new(from.clone(), dest.clone(), payload);
         let id = self.sender.send(Output(message)).await?;
-        self.pending_request.insert(id, tx);
+        self.pending_request.insert(
+            id,
+            PendingRequest {
+                from,
+                dest,
+                deadline: self.clock.now() + self.timeout,
+                tx,
+            },
+        );
 
         Ok(rx)
     }
@@ -46,14 +152,119 @@ impl<P: maelstrom_protocol::Payload + 'static> xtra::Handler<Request<P>> for Ser
 
 #[async_trait::async_trait]
 impl<P: maelstrom_protocol::Payload + 'static> xtra::Handler<Response<P>> for Service<P> {
-    async fn handle(&mut self, Response(message): Response<P>, _ctx: &mut xtra::Context<Self>) {
+    async fn handle(
+        &mut self,
+        Response(message): Response<P>,
+        _ctx: &mut xtra::Context<Self>,
+    ) -> Option<maelstrom_protocol::Message<maelstrom_protocol::Reply<P>>> {
         let in_reply_to = match message.body.in_reply_to {
             Some(v) => v,
-            None => return,
+            None => return Some(message),
         };
 
-        if let Some(tx) = self.pending_request.remove(&in_reply_to) {
-            let _ = tx.send(message);
+        match self.pending_request.remove(&in_reply_to) {
+            Some(pending) => {
+                let _ = pending.tx.send(message);
+                None
+            }
+            None => Some(message),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: maelstrom_protocol::Payload + 'static> xtra::Handler<Expire> for Service<P> {
+    async fn handle(&mut self, _: Expire, _ctx: &mut xtra::Context<Self>) {
+        let now = self.clock.now();
+
+        // drop requests whose caller is no longer waiting
+        self.pending_request
+            .retain(|_, pending| !pending.tx.is_closed());
+
+        let expired: Vec<_> = self
+            .pending_request
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired {
+            let Some(pending) = self.pending_request.remove(&id) else {
+                continue;
+            };
+
+            let mut message = maelstrom_protocol::Message::new(
+                pending.dest,
+                pending.from,
+                maelstrom_protocol::Reply::Err(maelstrom_protocol::Error::new(
+                    maelstrom_protocol::ErrorCode::Timeout,
+                    "request timed out",
+                )),
+            );
+            message.body.in_reply_to = Some(id);
+
+            let _ = pending.tx.send(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::ManualClock;
+    use serde::{Deserialize, Serialize};
+    use xtra::Actor;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Payload {
+        Ping,
+    }
+
+    impl maelstrom_protocol::Payload for Payload {}
+
+    /// Lets every task run until it waits on something; the paused tokio
+    /// clock only moves on once they all do.
+    async fn settle() {
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_time_out_only_as_the_clock_is_advanced() {
+        let (writer, _output) = tokio::io::duplex(1 << 16);
+        let clock = Arc::new(ManualClock::new());
+        let (sender, manager) = Sender::from_writer(writer).create(None).run();
+        tokio::spawn(manager);
+        let (service, manager) = Service::<Payload>::named("svc", sender.downgrade())
+            .with_clock(clock.clone())
+            .create(None)
+            .run();
+        tokio::spawn(manager);
+
+        let mut reply = service
+            .send(Request("n1".to_string(), Payload::Ping))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // real time passing does not matter
+        settle().await;
+        assert!(reply.try_recv().is_err());
+
+        clock.advance(DEFAULT_TIMEOUT - Duration::from_millis(1));
+        settle().await;
+        assert!(reply.try_recv().is_err());
+
+        clock.advance(EXPIRE_INTERVAL);
+        settle().await;
+        let reply = reply.try_recv().expect("request timed out");
+        assert_eq!((reply.src.as_str(), reply.dst.as_str()), ("svc", "n1"));
+        assert_eq!(reply.body.in_reply_to, Some(0));
+        match reply.body.payload {
+            maelstrom_protocol::Reply::Err(error) => {
+                assert_eq!(error.code, maelstrom_protocol::ErrorCode::Timeout)
+            }
+            reply => panic!("expected a timeout, got {reply:?}"),
         }
     }
 }
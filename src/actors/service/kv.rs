@@ -0,0 +1,226 @@
+use super::{Request, Service};
+use crate::{
+    actors::{rpc, Sender},
+    maelstrom_protocol,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Request and reply bodies shared by Maelstrom's key-value services.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum KvPayload {
+    Read {
+        key: String,
+    },
+    ReadOk {
+        value: serde_json::Value,
+    },
+    Write {
+        key: String,
+        value: serde_json::Value,
+    },
+    WriteOk,
+    Cas {
+        key: String,
+        from: serde_json::Value,
+        to: serde_json::Value,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+    #[serde(other)]
+    Unknown,
+}
+
+impl maelstrom_protocol::Payload for KvPayload {}
+
+/// The payload of one of the key-value services. Each service has its own, so
+/// a client of one cannot be handed another.
+pub trait KvService:
+    maelstrom_protocol::Payload + From<KvPayload> + Into<KvPayload> + 'static
+{
+}
+
+/// Declares one of Maelstrom's key-value services: the service type, and a
+/// payload wrapping [`KvPayload`] that only that service accepts.
+macro_rules! kv_service {
+    ($(#[$doc:meta])* $service:ident, $payload:ident, $name:literal) => {
+        $(#[$doc])*
+        pub type $service = Service<$payload>;
+
+        #[doc = concat!("A [`KvPayload`] sent to or received from `", $name, "`.")]
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $payload(pub KvPayload);
+
+        impl maelstrom_protocol::Payload for $payload {}
+
+        impl From<KvPayload> for $payload {
+            fn from(payload: KvPayload) -> Self {
+                Self(payload)
+            }
+        }
+
+        impl From<$payload> for KvPayload {
+            fn from(payload: $payload) -> Self {
+                payload.0
+            }
+        }
+
+        impl KvService for $payload {}
+
+        impl $service {
+            pub fn new(sender: xtra::WeakAddress<Sender>) -> Self {
+                Self::named($name, sender)
+            }
+        }
+    };
+}
+
+kv_service!(
+    /// Sequentially consistent key-value store.
+    SeqKv,
+    SeqKvPayload,
+    "seq-kv"
+);
+kv_service!(
+    /// Linearizable key-value store.
+    LinKv,
+    LinKvPayload,
+    "lin-kv"
+);
+kv_service!(
+    /// Last-write-wins key-value store.
+    LwwKv,
+    LwwKvPayload,
+    "lww-kv"
+);
+
+/// Why a key-value request did not succeed.
+#[derive(Debug, Clone)]
+pub enum KvError {
+    KeyDoesNotExist,
+    PreconditionFailed,
+    Timeout,
+    Other(maelstrom_protocol::Error),
+}
+
+impl From<maelstrom_protocol::Error> for KvError {
+    fn from(error: maelstrom_protocol::Error) -> Self {
+        match error.code {
+            maelstrom_protocol::ErrorCode::KeyDoesNotExist => Self::KeyDoesNotExist,
+            maelstrom_protocol::ErrorCode::PreconditionFailed => Self::PreconditionFailed,
+            maelstrom_protocol::ErrorCode::Timeout => Self::Timeout,
+            _ => Self::Other(error),
+        }
+    }
+}
+
+impl std::fmt::Display for KvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeyDoesNotExist => write!(f, "key does not exist"),
+            Self::PreconditionFailed => write!(f, "precondition failed"),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::Other(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+/// Typed access to any of Maelstrom's key-value services.
+pub struct KvClient<P: KvService> {
+    node_id: String,
+    service: xtra::WeakAddress<Service<P>>,
+}
+
+impl<P: KvService> Clone for KvClient<P> {
+    fn clone(&self) -> Self {
+        Self {
+            node_id: self.node_id.clone(),
+            service: self.service.clone(),
+        }
+    }
+}
+
+impl<P: KvService> KvClient<P> {
+    pub fn new(node_id: String, service: xtra::WeakAddress<Service<P>>) -> Self {
+        Self { node_id, service }
+    }
+
+    pub async fn read<T: DeserializeOwned>(&self, key: impl Into<String>) -> Result<T, KvError> {
+        match self.request(KvPayload::Read { key: key.into() }).await? {
+            KvPayload::ReadOk { value } => serde_json::from_value(value).map_err(|e| {
+                KvError::Other(maelstrom_protocol::Error::new(
+                    maelstrom_protocol::ErrorCode::MalformedRequest,
+                    e.to_string(),
+                ))
+            }),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub async fn write<T: Serialize>(
+        &self,
+        key: impl Into<String>,
+        value: &T,
+    ) -> Result<(), KvError> {
+        let payload = KvPayload::Write {
+            key: key.into(),
+            value: to_value(value)?,
+        };
+
+        match self.request(payload).await? {
+            KvPayload::WriteOk => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Replaces `from` with `to`; with `create` a missing key is set to `to`.
+    pub async fn cas<T: Serialize>(
+        &self,
+        key: impl Into<String>,
+        from: &T,
+        to: &T,
+        create: bool,
+    ) -> Result<(), KvError> {
+        let payload = KvPayload::Cas {
+            key: key.into(),
+            from: to_value(from)?,
+            to: to_value(to)?,
+            create_if_not_exists: create,
+        };
+
+        match self.request(payload).await? {
+            KvPayload::CasOk => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    async fn request(&self, payload: KvPayload) -> Result<KvPayload, KvError> {
+        let message = rpc::call(
+            &self.service,
+            Request(self.node_id.clone(), P::from(payload)),
+        )
+        .await?;
+        Ok(message.body.payload.into())
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Result<serde_json::Value, KvError> {
+    serde_json::to_value(value).map_err(|e| {
+        KvError::Other(maelstrom_protocol::Error::new(
+            maelstrom_protocol::ErrorCode::MalformedRequest,
+            e.to_string(),
+        ))
+    })
+}
+
+fn unexpected(payload: KvPayload) -> KvError {
+    KvError::Other(maelstrom_protocol::Error::new(
+        maelstrom_protocol::ErrorCode::Crash,
+        format!("unexpected reply: {payload:?}"),
+    ))
+}
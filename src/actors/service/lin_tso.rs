@@ -0,0 +1,54 @@
+use super::{Request, Service};
+use crate::{
+    actors::{rpc, Sender},
+    maelstrom_protocol,
+};
+use serde::{Deserialize, Serialize};
+
+/// Linearizable timestamp oracle.
+pub type LinTso = Service<LinTsoPayload>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum LinTsoPayload {
+    Ts,
+    TsOk { ts: u64 },
+}
+
+impl maelstrom_protocol::Payload for LinTsoPayload {}
+
+impl LinTso {
+    pub fn new(sender: xtra::WeakAddress<Sender>) -> Self {
+        Self::named("lin-tso", sender)
+    }
+}
+
+/// Hands out strictly increasing timestamps from `lin-tso`.
+#[derive(Clone)]
+pub struct LinTsoClient {
+    node_id: String,
+    service: xtra::WeakAddress<LinTso>,
+}
+
+impl LinTsoClient {
+    pub fn new(node_id: String, service: xtra::WeakAddress<LinTso>) -> Self {
+        Self { node_id, service }
+    }
+
+    pub async fn ts(&self) -> Result<u64, maelstrom_protocol::Error> {
+        let message = rpc::call(
+            &self.service,
+            Request(self.node_id.clone(), LinTsoPayload::Ts),
+        )
+        .await?;
+
+        match message.body.payload {
+            LinTsoPayload::TsOk { ts } => Ok(ts),
+            other => Err(maelstrom_protocol::Error::new(
+                maelstrom_protocol::ErrorCode::Crash,
+                format!("unexpected reply: {other:?}"),
+            )),
+        }
+    }
+}
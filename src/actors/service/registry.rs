@@ -0,0 +1,78 @@
+use super::{Response, Service};
+use crate::maelstrom_protocol;
+use std::{any::Any, collections::HashMap};
+use xtra::Actor;
+
+/// Delivers a raw reply from a service to the actor that awaits it.
+pub trait Route: Send + Sync {
+    fn route(&self, envelope: maelstrom_protocol::Envelope) -> serde_json::Result<()>;
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<P: maelstrom_protocol::Payload + 'static> Route for xtra::Address<Service<P>> {
+    fn route(&self, envelope: maelstrom_protocol::Envelope) -> serde_json::Result<()> {
+        let message = envelope.parse::<maelstrom_protocol::Reply<P>>()?;
+        // a stopped service has no one left waiting for the reply
+        let _ = self.do_send(Response(message));
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Running services, keyed by the name they answer from.
+#[derive(Default)]
+pub struct Services {
+    routes: HashMap<&'static str, Box<dyn Route>>,
+}
+
+impl Services {
+    /// Spawns `service` and routes every message from its name to it.
+    ///
+    /// Panics if a service with the same name is already running.
+    pub fn spawn<P: maelstrom_protocol::Payload + 'static>(
+        &mut self,
+        service: Service<P>,
+    ) -> xtra::Address<Service<P>> {
+        let name = service.name;
+        assert!(
+            !self.contains(name),
+            "service {name} is registered more than once"
+        );
+
+        let address = service.create(None).spawn(&mut xtra::spawn::Tokio::Global);
+        self.routes.insert(name, Box::new(address.clone()));
+        address
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.routes.contains_key(name)
+    }
+
+    /// The service running as `name`, unless there is none or it speaks
+    /// another payload.
+    pub fn get<P: maelstrom_protocol::Payload + 'static>(
+        &self,
+        name: &str,
+    ) -> Option<xtra::WeakAddress<Service<P>>> {
+        self.routes
+            .get(name)?
+            .as_any()
+            .downcast_ref::<xtra::Address<Service<P>>>()
+            .map(|address| address.downgrade())
+    }
+
+    /// Hands the envelope to the service it came from, or back if there is none.
+    pub fn route(
+        &self,
+        envelope: maelstrom_protocol::Envelope,
+    ) -> Result<serde_json::Result<()>, maelstrom_protocol::Envelope> {
+        match self.routes.get(envelope.src.as_str()) {
+            Some(route) => Ok(route.route(envelope)),
+            None => Err(envelope),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, DuplexStream},
+    net::TcpStream,
+};
+
+/// Where a node reads its input lines from and writes its output lines to.
+pub trait Transport {
+    type Reader: AsyncRead + Send + Unpin + 'static;
+    type Writer: AsyncWrite + Send + Unpin + 'static;
+
+    fn split(self) -> (Self::Reader, Self::Writer);
+}
+
+/// The process's stdin and stdout, as used by Maelstrom.
+pub struct Stdio;
+
+impl Transport for Stdio {
+    type Reader = io::Stdin;
+    type Writer = io::Stdout;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        (io::stdin(), io::stdout())
+    }
+}
+
+impl<R, W> Transport for (R, W)
+where
+    R: AsyncRead + Send + Unpin + 'static,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    type Reader = R;
+    type Writer = W;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        self
+    }
+}
+
+/// One end of an in-process pipe, see [`tokio::io::duplex`].
+impl Transport for DuplexStream {
+    type Reader = io::ReadHalf<DuplexStream>;
+    type Writer = io::WriteHalf<DuplexStream>;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        io::split(self)
+    }
+}
+
+impl Transport for TcpStream {
+    type Reader = tokio::net::tcp::OwnedReadHalf;
+    type Writer = tokio::net::tcp::OwnedWriteHalf;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        self.into_split()
+    }
+}
+
+#[cfg(unix)]
+impl Transport for tokio::net::UnixStream {
+    type Reader = tokio::net::unix::OwnedReadHalf;
+    type Writer = tokio::net::unix::OwnedWriteHalf;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        self.into_split()
+    }
+}
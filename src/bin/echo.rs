@@ -1,6 +1,5 @@
 use gossip_glomers::{actors, maelstrom_protocol};
 use serde::{Deserialize, Serialize};
-use xtra::Actor;
 
 struct EchoNode;
 
@@ -10,40 +9,29 @@ impl xtra::Actor for EchoNode {}
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 enum Payload {
-    Init(maelstrom_protocol::InitPayload),
-    InitOk,
-    Echo {
-        echo: String,
-    },
-    EchoOk {
-        echo: String,
-    },
-    #[serde(other)]
-    Unknown,
+    Echo { echo: String },
+    EchoOk { echo: String },
 }
 
 impl maelstrom_protocol::Payload for Payload {}
 
 #[async_trait::async_trait]
-impl xtra::Handler<maelstrom_protocol::Message<Payload>> for EchoNode {
+impl xtra::Handler<actors::Input<Payload>> for EchoNode {
     async fn handle(
         &mut self,
-        message: maelstrom_protocol::Message<Payload>,
+        actors::Input(message, cx): actors::Input<Payload>,
         _ctx: &mut xtra::Context<Self>,
-    ) -> Option<maelstrom_protocol::Message<Payload>> {
+    ) {
         match &message.body.payload {
-            Payload::Init(_) => Some(message.make_response(Payload::InitOk)),
             Payload::Echo { echo } => {
-                Some(message.make_response(Payload::EchoOk { echo: echo.clone() }))
+                cx.reply(Payload::EchoOk { echo: echo.clone() });
             }
-            Payload::EchoOk { echo: _ } | Payload::InitOk | Payload::Unknown => None,
+            Payload::EchoOk { echo: _ } => {}
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let actors = actors::spawn_actors();
-    let node = EchoNode.create(None).spawn(&mut xtra::spawn::Tokio::Global);
-    actors::run_io(node, actors).await;
+    actors::Runtime::builder().node(|_, _| EchoNode).run().await;
 }
@@ -1,22 +1,24 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use gossip_glomers::{actors, maelstrom_protocol};
 use serde::{Deserialize, Serialize};
-use xtra::Actor;
 
 struct EfficientBroadcastNode {
     sender: xtra::WeakAddress<actors::Sender>,
-    init: Option<maelstrom_protocol::InitPayload>,
+    node: actors::NodeContext,
     neighbours: HashSet<String>,
     messages: HashSet<usize>,
     unknown_messages: HashMap<String, HashSet<usize>>,
 }
 
 impl EfficientBroadcastNode {
-    pub fn new(sender: xtra::WeakAddress<actors::Sender>) -> Self {
+    pub fn new(node: actors::NodeContext, sender: xtra::WeakAddress<actors::Sender>) -> Self {
         Self {
             sender,
-            init: None,
+            node,
             neighbours: HashSet::new(),
             messages: HashSet::new(),
             unknown_messages: HashMap::new(),
@@ -24,15 +26,7 @@ impl EfficientBroadcastNode {
     }
 }
 
-#[async_trait::async_trait]
-impl xtra::Actor for EfficientBroadcastNode {
-    async fn started(&mut self, ctx: &mut xtra::Context<Self>) {
-        tokio::spawn(
-            ctx.notify_interval(std::time::Duration::from_millis(1000), || Gossip)
-                .expect("notify_interval failed"),
-        );
-    }
-}
+impl xtra::Actor for EfficientBroadcastNode {}
 
 struct Gossip;
 
@@ -44,8 +38,6 @@ impl xtra::Message for Gossip {
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 enum Payload {
-    Init(maelstrom_protocol::InitPayload),
-    InitOk,
     Broadcast {
         message: usize,
     },
@@ -65,8 +57,6 @@ enum Payload {
     GossipOk {
         messages: HashSet<usize>,
     },
-    #[serde(other)]
-    Unknown,
 }
 
 impl maelstrom_protocol::Payload for Payload {}
@@ -74,10 +64,6 @@ impl maelstrom_protocol::Payload for Payload {}
 #[async_trait::async_trait]
 impl xtra::Handler<Gossip> for EfficientBroadcastNode {
     async fn handle(&mut self, _: Gossip, _ctx: &mut xtra::Context<Self>) {
-        let Some(id) = self.init.as_ref().map(|v| v.node_id.clone()) else {
-            return;
-        };
-
         let mut unknown_messages = HashSet::new();
 
         self.neighbours.iter().for_each(|n| {
@@ -90,7 +76,7 @@ impl xtra::Handler<Gossip> for EfficientBroadcastNode {
 
         for n in &self.neighbours {
             let message = maelstrom_protocol::Message::new(
-                id.clone(),
+                self.node.node_id.clone(),
                 n.clone(),
                 Payload::Gossip {
                     recipients: self.neighbours.clone(),
@@ -98,25 +84,23 @@ impl xtra::Handler<Gossip> for EfficientBroadcastNode {
                 },
             );
 
-            self.sender
-                .do_send(actors::Output(message))
-                .expect("could not send output to sender");
+            // the writer only goes away once the node is shutting down
+            if self.sender.do_send(actors::Output(message)).is_err() {
+                eprintln!("could not gossip: {}", actors::Error::WriterStopped);
+                return;
+            }
         }
     }
 }
 
 #[async_trait::async_trait]
-impl xtra::Handler<maelstrom_protocol::Message<Payload>> for EfficientBroadcastNode {
+impl xtra::Handler<actors::Input<Payload>> for EfficientBroadcastNode {
     async fn handle(
         &mut self,
-        message: maelstrom_protocol::Message<Payload>,
+        actors::Input(message, cx): actors::Input<Payload>,
         _ctx: &mut xtra::Context<Self>,
-    ) -> Option<maelstrom_protocol::Message<Payload>> {
+    ) {
         match &message.body.payload {
-            Payload::Init(init) => {
-                self.init.replace(init.clone());
-                Some(message.make_response(Payload::InitOk))
-            }
             Payload::Broadcast { message: m } => {
                 self.messages.insert(*m);
 
@@ -127,23 +111,21 @@ impl xtra::Handler<maelstrom_protocol::Message<Payload>> for EfficientBroadcastN
                         .insert(*m);
                 }
 
-                Some(message.make_response(Payload::BroadcastOk))
+                cx.reply(Payload::BroadcastOk);
             }
-            Payload::Read => Some(message.make_response(Payload::ReadOk {
+            Payload::Read => cx.reply(Payload::ReadOk {
                 messages: self.messages.clone(),
-            })),
+            }),
             Payload::Topology { topology } => {
-                if let Some(id) = self.init.as_ref().map(|v| v.node_id.clone()) {
-                    self.neighbours.extend(topology[&id].clone());
-                }
+                self.neighbours.extend(topology[&self.node.node_id].clone());
 
-                Some(message.make_response(Payload::TopologyOk))
+                cx.reply(Payload::TopologyOk);
             }
             Payload::Gossip {
                 recipients,
                 messages,
             } => match messages.is_empty() {
-                true => None,
+                true => {}
                 false => {
                     self.messages.extend(messages.clone());
 
@@ -158,32 +140,27 @@ impl xtra::Handler<maelstrom_protocol::Message<Payload>> for EfficientBroadcastN
                             .extend(messages.clone());
                     }
 
-                    Some(message.make_response(Payload::GossipOk {
+                    cx.reply(Payload::GossipOk {
                         messages: messages.clone(),
-                    }))
+                    });
                 }
             },
             Payload::GossipOk { messages } => {
                 if let Some(unknown_messages) = self.unknown_messages.get_mut(&message.src) {
                     unknown_messages.retain(|m| !messages.contains(m));
                 }
-                None
             }
 
-            Payload::InitOk
-            | Payload::BroadcastOk
-            | Payload::ReadOk { .. }
-            | Payload::TopologyOk
-            | Payload::Unknown => None,
+            Payload::BroadcastOk | Payload::ReadOk { .. } | Payload::TopologyOk => {}
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let actors = actors::spawn_actors();
-    let node = EfficientBroadcastNode::new(actors.0.downgrade())
-        .create(None)
-        .spawn(&mut xtra::spawn::Tokio::Global);
-    actors::run_io(node, actors).await;
+    actors::Runtime::builder()
+        .node(|node, runtime| EfficientBroadcastNode::new(node, runtime.sender()))
+        .every(Duration::from_millis(1000), || Gossip)
+        .run()
+        .await;
 }
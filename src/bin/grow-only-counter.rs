@@ -1,39 +1,34 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use gossip_glomers::{
     actors::{
         self,
-        service::{Request, SeqKv, SeqKvPayload},
+        service::{KvClient, SeqKv, SeqKvPayload},
     },
     maelstrom_protocol,
 };
 use serde::{Deserialize, Serialize};
-use xtra::Actor;
 
 struct GrowOnlyCounterNode {
     counter: usize,
-    other_counters: usize,
-    init: Option<maelstrom_protocol::InitPayload>,
-    seq_kv: xtra::WeakAddress<SeqKv>,
+    /// The last value read for each peer's counter.
+    other_counters: HashMap<String, usize>,
+    /// Whether a fetch is still running.
+    fetching: bool,
+    node: actors::NodeContext,
+    seq_kv: KvClient<SeqKvPayload>,
 }
 
-#[async_trait::async_trait]
-impl xtra::Actor for GrowOnlyCounterNode {
-    async fn started(&mut self, ctx: &mut xtra::Context<Self>) {
-        tokio::spawn(
-            ctx.notify_interval(Duration::from_millis(1000), || FetchCounters)
-                .expect("notify_interval failed"),
-        );
-    }
-}
+impl xtra::Actor for GrowOnlyCounterNode {}
 
 impl GrowOnlyCounterNode {
-    pub fn new(seq_kv: xtra::WeakAddress<SeqKv>) -> Self {
+    pub fn new(node: actors::NodeContext, seq_kv: xtra::WeakAddress<SeqKv>) -> Self {
         Self {
             counter: 0,
-            other_counters: 0,
-            init: None,
-            seq_kv,
+            other_counters: HashMap::new(),
+            fetching: false,
+            seq_kv: KvClient::new(node.node_id.clone(), seq_kv),
+            node,
         }
     }
 }
@@ -44,103 +39,94 @@ impl xtra::Message for FetchCounters {
     type Result = ();
 }
 
+/// The peer counters a fetch managed to read.
+struct Fetched(Vec<(String, usize)>);
+
+impl xtra::Message for Fetched {
+    type Result = ();
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 enum Payload {
-    Init(maelstrom_protocol::InitPayload),
-    InitOk,
-    Add {
-        delta: usize,
-    },
+    Add { delta: usize },
     AddOk,
     Read,
-    ReadOk {
-        value: usize,
-    },
-    #[serde(other)]
-    Unknown,
+    ReadOk { value: usize },
 }
 
 impl maelstrom_protocol::Payload for Payload {}
 
 #[async_trait::async_trait]
 impl xtra::Handler<FetchCounters> for GrowOnlyCounterNode {
-    async fn handle(&mut self, _: FetchCounters, _ctx: &mut xtra::Context<Self>) {
-        let (node_id, others) = match self.init.as_ref().map(|v| {
-            (
-                v.node_id.clone(),
-                v.node_ids
-                    .iter()
-                    .filter(|i| **i != v.node_id)
-                    .map(|v| v.clone())
-                    .collect::<Vec<_>>(),
-            )
-        }) {
-            Some(v) => v,
-            None => return,
+    async fn handle(&mut self, _: FetchCounters, ctx: &mut xtra::Context<Self>) {
+        if self.fetching {
+            return;
+        }
+        let Ok(address) = ctx.address() else {
+            return;
         };
+        self.fetching = true;
+
+        // the service calls run off the handler, so adds and reads are not
+        // held up behind them
+        let address = address.downgrade();
+        let seq_kv = self.seq_kv.clone();
+        let (node_id, counter) = (self.node.node_id.clone(), self.counter);
+        let peers: Vec<_> = self.node.peers().cloned().collect();
+        tokio::spawn(async move {
+            let _ = seq_kv.write(&node_id, &counter).await;
+
+            let reads = peers.into_iter().map(|peer| async {
+                let value = seq_kv.read::<usize>(&peer).await;
+                value.ok().map(|value| (peer, value))
+            });
+            let fetched = futures::future::join_all(reads).await;
+            let _ = address.do_send(Fetched(fetched.into_iter().flatten().collect()));
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl xtra::Handler<Fetched> for GrowOnlyCounterNode {
+    async fn handle(&mut self, Fetched(counters): Fetched, _ctx: &mut xtra::Context<Self>) {
+        self.fetching = false;
 
-        // write
-        let _ = self.seq_kv.do_send(Request(
-            node_id.clone(),
-            SeqKvPayload::Write {
-                key: node_id.clone(),
-                value: self.counter.to_string(),
-            },
-        ));
-
-        // read others
-        self.other_counters = 0;
-        for o in &others {
-            let Ok(Ok(resp)) = self
-                .seq_kv
-                .send(Request(
-                    node_id.clone(),
-                    actors::service::SeqKvPayload::Read { key: o.clone() },
-                ))
-                .await else {
-                continue;
-            };
-
-            let Ok(SeqKvPayload::ReadOk { value }) = resp.await.map(|v| v.body.payload) else {
-                continue;
-            };
-
-            self.other_counters += value.parse::<usize>().unwrap_or_default();
+        // peers that could not be read keep their last value, and a stale
+        // read never takes a counter back
+        for (peer, value) in counters {
+            let known = self.other_counters.entry(peer).or_default();
+            *known = value.max(*known);
         }
     }
 }
 
 #[async_trait::async_trait]
-impl xtra::Handler<maelstrom_protocol::Message<Payload>> for GrowOnlyCounterNode {
+impl xtra::Handler<actors::Input<Payload>> for GrowOnlyCounterNode {
     async fn handle(
         &mut self,
-        message: maelstrom_protocol::Message<Payload>,
+        actors::Input(message, cx): actors::Input<Payload>,
         _ctx: &mut xtra::Context<Self>,
-    ) -> Option<maelstrom_protocol::Message<Payload>> {
+    ) {
         match &message.body.payload {
-            Payload::Init(init) => {
-                self.init.replace(init.clone());
-                Some(message.make_response(Payload::InitOk))
-            }
             Payload::Add { delta } => {
                 self.counter += delta;
-                Some(message.make_response(Payload::AddOk))
+                cx.reply(Payload::AddOk);
             }
-            Payload::Read => Some(message.make_response(Payload::ReadOk {
-                value: self.counter + self.other_counters,
-            })),
-            Payload::AddOk | Payload::ReadOk { .. } | Payload::InitOk | Payload::Unknown => None,
+            Payload::Read => cx.reply(Payload::ReadOk {
+                value: self.counter + self.other_counters.values().sum::<usize>(),
+            }),
+            Payload::AddOk | Payload::ReadOk { .. } => {}
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let actors = actors::spawn_actors();
-    let node = GrowOnlyCounterNode::new(actors.1.downgrade())
-        .create(None)
-        .spawn(&mut xtra::spawn::Tokio::Global);
-    actors::run_io(node, actors).await;
+    actors::Runtime::builder()
+        .node(|node, runtime| GrowOnlyCounterNode::new(node, runtime.seq_kv()))
+        .every(Duration::from_millis(1000), || FetchCounters)
+        .run()
+        .await;
 }
@@ -0,0 +1,540 @@
+//! Drives a workload against nodes behind the router and reports how it went.
+//!
+//! ```text
+//! loadgen <broadcast|g-counter|kafka|unique-ids> [--port PORT] [--rate OPS_PER_SEC]
+//!         [--concurrency N] [--time-limit SECS] [--settle SECS] [--seed N]
+//! ```
+//!
+//! msgs-per-op counts the messages nodes sent each other, as Maelstrom does.
+//! For broadcast, stable latency is how long a message took to show up in
+//! reads on every node; final reads on every node close the run. They are
+//! reported apart from the workload, whose throughput only counts its own ops.
+
+use gossip_glomers::rng::Rng;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+    sync::{oneshot, Semaphore},
+};
+
+/// The client id requests are sent from.
+const CLIENT: &str = "c1";
+
+/// How long a request may go unanswered before it counts as timed out.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Workload {
+    Broadcast,
+    GCounter,
+    Kafka,
+    UniqueIds,
+}
+
+struct Options {
+    workload: Workload,
+    port: u16,
+    /// The time between operations.
+    period: Duration,
+    concurrency: usize,
+    time_limit: Duration,
+    /// How long the cluster gets to settle before the final reads.
+    settle: Duration,
+    seed: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    Failed,
+    TimedOut,
+}
+
+struct Sample {
+    kind: &'static str,
+    latency: Duration,
+    outcome: Outcome,
+}
+
+/// A connection to the router with any number of requests in flight.
+struct Client {
+    writer: tokio::sync::Mutex<OwnedWriteHalf>,
+    pending: Arc<Mutex<HashMap<usize, oneshot::Sender<Value>>>>,
+    next_id: AtomicUsize,
+}
+
+/// When each broadcast was sent and when each node first read it.
+#[derive(Default)]
+struct Visibility {
+    sent: HashMap<u64, Instant>,
+    seen: HashMap<u64, HashMap<String, Instant>>,
+}
+
+/// The offsets Kafka nodes handed out and returned, per key.
+#[derive(Default)]
+struct Offsets {
+    sent: HashMap<String, Vec<u64>>,
+    polled: HashMap<String, u64>,
+}
+
+#[derive(Default)]
+struct Results {
+    samples: Vec<Sample>,
+    visibility: Visibility,
+    offsets: Offsets,
+}
+
+#[tokio::main]
+async fn main() {
+    let options = parse_args();
+    let client = match Client::connect(options.port).await {
+        Ok(client) => Arc::new(client),
+        Err(e) => {
+            eprintln!("could not connect to the router: {e}");
+            process::exit(1);
+        }
+    };
+
+    let Some(before) = client.request("router", json!({"type": "stats"})).await else {
+        eprintln!("the router did not answer");
+        process::exit(1);
+    };
+    let node_ids: Vec<String> =
+        serde_json::from_value(before["node_ids"].clone()).expect("router reports node ids");
+
+    let results = Arc::new(Mutex::new(Results::default()));
+    let permits = Arc::new(Semaphore::new(options.concurrency));
+    let mut rng = Rng::new(options.seed);
+    let mut next_value = 0;
+
+    let started = Instant::now();
+    let mut interval = tokio::time::interval(options.period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    while started.elapsed() < options.time_limit {
+        interval.tick().await;
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+
+        next_value += 1;
+        let node = node_ids[rng.next_u64() as usize % node_ids.len()].clone();
+        let (kind, body) = {
+            let results = results.lock().expect("results lock");
+            make_op(options.workload, &mut rng, next_value, &results.offsets)
+        };
+
+        let client = client.clone();
+        let results = results.clone();
+        tokio::spawn(async move {
+            perform(&client, &results, node, kind, body).await;
+            drop(permit);
+        });
+    }
+
+    // wait for everything in flight
+    let _ = permits.acquire_many(options.concurrency as u32).await;
+    let elapsed = started.elapsed();
+    let ops = results.lock().expect("results lock").samples.len();
+
+    if matches!(options.workload, Workload::Broadcast | Workload::GCounter) {
+        tokio::time::sleep(options.settle).await;
+        for node in &node_ids {
+            perform(
+                &client,
+                &results,
+                node.clone(),
+                "read",
+                json!({"type": "read"}),
+            )
+            .await;
+        }
+    }
+
+    let Some(after) = client.request("router", json!({"type": "stats"})).await else {
+        eprintln!("the router did not answer");
+        process::exit(1);
+    };
+    let node_messages = |stats: &Value| stats["node_messages"].as_u64().unwrap_or_default();
+    let node_messages = node_messages(&after) - node_messages(&before);
+
+    let mut results = results.lock().expect("results lock");
+    let final_reads = results.samples.split_off(ops);
+    report(
+        &options,
+        elapsed,
+        node_messages,
+        &results,
+        &final_reads,
+        node_ids.len(),
+    );
+}
+
+impl Client {
+    async fn connect(port: u16) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(("127.0.0.1", port)).await?;
+        let (reader, writer) = stream.into_split();
+        let pending: Arc<Mutex<HashMap<usize, oneshot::Sender<Value>>>> = Arc::default();
+
+        let replies = pending.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                    continue;
+                };
+                let Some(id) = message["body"]["in_reply_to"].as_u64() else {
+                    continue;
+                };
+                let waiting = replies.lock().expect("pending lock").remove(&(id as usize));
+                if let Some(waiting) = waiting {
+                    let _ = waiting.send(message["body"].clone());
+                }
+            }
+        });
+
+        Ok(Self {
+            writer: tokio::sync::Mutex::new(writer),
+            pending,
+            next_id: AtomicUsize::new(1),
+        })
+    }
+
+    /// Sends a request and waits for the reply's body, or `None` on timeout.
+    async fn request(&self, dest: &str, mut body: Value) -> Option<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        body["msg_id"] = id.into();
+
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().expect("pending lock").insert(id, tx);
+
+        let mut line = serde_json::to_vec(&json!({"src": CLIENT, "dest": dest, "body": body}))
+            .expect("could not serialize request");
+        line.push(b'\n');
+        if let Err(e) = self.writer.lock().await.write_all(&line).await {
+            eprintln!("could not send a request: {e}");
+            process::exit(1);
+        }
+
+        let reply = tokio::time::timeout(REQUEST_TIMEOUT, rx).await;
+        self.pending.lock().expect("pending lock").remove(&id);
+        reply.ok().and_then(Result::ok)
+    }
+}
+
+async fn perform(
+    client: &Client,
+    results: &Mutex<Results>,
+    node: String,
+    kind: &'static str,
+    body: Value,
+) {
+    let message = body["message"].as_u64();
+    let key = body["key"].as_str().map(str::to_string);
+    let invoked = Instant::now();
+    if let Some(message) = message {
+        let mut results = results.lock().expect("results lock");
+        results.visibility.sent.insert(message, invoked);
+    }
+
+    let reply = client.request(&node, body).await;
+    let latency = invoked.elapsed();
+
+    let mut results = results.lock().expect("results lock");
+    let outcome = match &reply {
+        Some(reply) if reply["type"] == "error" => Outcome::Failed,
+        Some(_) => Outcome::Ok,
+        None => Outcome::TimedOut,
+    };
+    results.samples.push(Sample {
+        kind,
+        latency,
+        outcome,
+    });
+
+    if let (Some(reply), Some(key)) = (&reply, key) {
+        if let Some(offset) = reply["offset"]
+            .as_u64()
+            .filter(|_| reply["type"] == "send_ok")
+        {
+            results.offsets.sent.entry(key).or_default().push(offset);
+        }
+    }
+    if let Some(msgs) = reply
+        .as_ref()
+        .filter(|reply| reply["type"] == "poll_ok")
+        .and_then(|reply| reply["msgs"].as_object())
+    {
+        for (key, msgs) in msgs {
+            let offsets = msgs.as_array().into_iter().flatten();
+            if let Some(highest) = offsets.filter_map(|msg| msg[0].as_u64()).max() {
+                let polled = results.offsets.polled.entry(key.clone()).or_default();
+                *polled = highest.max(*polled);
+            }
+        }
+    }
+
+    if let Some(messages) = reply
+        .as_ref()
+        .filter(|reply| reply["type"] == "read_ok")
+        .and_then(|reply| reply["messages"].as_array())
+    {
+        let now = Instant::now();
+        for message in messages.iter().filter_map(Value::as_u64) {
+            results
+                .visibility
+                .seen
+                .entry(message)
+                .or_default()
+                .entry(node.clone())
+                .or_insert(now);
+        }
+    }
+}
+
+/// The next operation: its name and the request body, without a `msg_id`.
+///
+/// Kafka polls pick up after the highest offset polled so far, or at the first
+/// offset sent, and commits cover everything polled.
+fn make_op(
+    workload: Workload,
+    rng: &mut Rng,
+    value: u64,
+    offsets: &Offsets,
+) -> (&'static str, Value) {
+    let key = format!("k{}", rng.next_u64() % 5);
+    match workload {
+        Workload::Broadcast => match rng.chance(0.5) {
+            true => ("broadcast", json!({"type": "broadcast", "message": value})),
+            false => ("read", json!({"type": "read"})),
+        },
+        Workload::GCounter => match rng.chance(0.5) {
+            true => ("add", json!({"type": "add", "delta": rng.next_u64() % 5})),
+            false => ("read", json!({"type": "read"})),
+        },
+        Workload::Kafka => match rng.next_u64() % 10 {
+            0..=4 => ("send", json!({"type": "send", "key": key, "msg": value})),
+            8 if !offsets.polled.is_empty() => (
+                "commit_offsets",
+                json!({"type": "commit_offsets", "offsets": offsets.polled}),
+            ),
+            5..=8 => {
+                let from = match offsets.polled.get(&key) {
+                    Some(polled) => polled + 1,
+                    None => offsets
+                        .sent
+                        .get(&key)
+                        .and_then(|sent| sent.iter().min().copied())
+                        .unwrap_or_default(),
+                };
+                ("poll", json!({"type": "poll", "offsets": {key: from}}))
+            }
+            _ => (
+                "list_committed_offsets",
+                json!({"type": "list_committed_offsets", "keys": [key]}),
+            ),
+        },
+        Workload::UniqueIds => ("generate", json!({"type": "generate"})),
+    }
+}
+
+fn report(
+    options: &Options,
+    elapsed: Duration,
+    node_messages: u64,
+    results: &Results,
+    final_reads: &[Sample],
+    nodes: usize,
+) {
+    let samples = &results.samples;
+    let count = |outcome| samples.iter().filter(|s| s.outcome == outcome).count();
+    let ops = samples.len();
+
+    println!(
+        "{ops} ops in {:.1}s ({:.1}/s), {} ok, {} failed, {} timed out",
+        elapsed.as_secs_f64(),
+        ops as f64 / elapsed.as_secs_f64(),
+        count(Outcome::Ok),
+        count(Outcome::Failed),
+        count(Outcome::TimedOut),
+    );
+    if !final_reads.is_empty() {
+        let ok = final_reads.iter().filter(|s| s.outcome == Outcome::Ok);
+        println!(
+            "{} final reads after {:.1}s, {} ok",
+            final_reads.len(),
+            options.settle.as_secs_f64(),
+            ok.count(),
+        );
+    }
+    println!(
+        "msgs-per-op {:.2} ({node_messages} between nodes)",
+        node_messages as f64 / ops.max(1) as f64
+    );
+    println!();
+
+    println!(
+        "{:<24}{:>10}{:>10}{:>10}{:>10}{:>10}",
+        "latency", "ops", "p50", "p95", "p99", "max"
+    );
+    let mut kinds: Vec<_> = samples.iter().map(|s| s.kind).collect();
+    kinds.sort();
+    kinds.dedup();
+    for kind in kinds {
+        let latencies = samples
+            .iter()
+            .filter(|s| s.kind == kind && s.outcome == Outcome::Ok)
+            .map(|s| s.latency);
+        print_latencies(kind, latencies.collect());
+    }
+    print_latencies(
+        "all",
+        samples
+            .iter()
+            .filter(|s| s.outcome == Outcome::Ok)
+            .map(|s| s.latency)
+            .collect(),
+    );
+    if !final_reads.is_empty() {
+        print_latencies(
+            "final read",
+            final_reads
+                .iter()
+                .filter(|s| s.outcome == Outcome::Ok)
+                .map(|s| s.latency)
+                .collect(),
+        );
+    }
+
+    if options.workload == Workload::Broadcast {
+        let visibility = &results.visibility;
+        let mut stable = Vec::new();
+        let mut unstable = 0;
+        for (message, sent) in &visibility.sent {
+            match visibility.seen.get(message) {
+                Some(seen) if seen.len() == nodes => {
+                    let at = seen.values().max().expect("seen by every node");
+                    stable.push(at.saturating_duration_since(*sent));
+                }
+                _ => unstable += 1,
+            }
+        }
+        print_latencies("stable", stable);
+        if unstable > 0 {
+            println!("{unstable} broadcasts never reached every node");
+        }
+    }
+}
+
+fn print_latencies(name: &str, mut latencies: Vec<Duration>) {
+    latencies.sort();
+    let percentile = |p: f64| {
+        let rank = (p * latencies.len() as f64).ceil() as usize;
+        latencies
+            .get(rank.saturating_sub(1))
+            .map(|latency| format!("{:.1}ms", latency.as_secs_f64() * 1000.0))
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    println!(
+        "{name:<24}{:>10}{:>10}{:>10}{:>10}{:>10}",
+        latencies.len(),
+        percentile(0.5),
+        percentile(0.95),
+        percentile(0.99),
+        percentile(1.0),
+    );
+}
+
+fn parse_args() -> Options {
+    let mut args = std::env::args().skip(1);
+    let workload = match args.next().as_deref() {
+        Some("broadcast") => Workload::Broadcast,
+        Some("g-counter") => Workload::GCounter,
+        Some("kafka") => Workload::Kafka,
+        Some("unique-ids") => Workload::UniqueIds,
+        _ => usage(),
+    };
+
+    let mut options = Options {
+        workload,
+        port: 7000,
+        period: Duration::from_millis(100),
+        concurrency: 10,
+        time_limit: Duration::from_secs(10),
+        settle: Duration::from_secs(5),
+        seed: 0,
+    };
+
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--port" => options.port = value.parse().unwrap_or_else(|_| usage()),
+            "--rate" => match value.parse().ok().and_then(period) {
+                Some(period) => options.period = period,
+                None => usage(),
+            },
+            "--concurrency" => match value.parse() {
+                Ok(concurrency) if concurrency > 0 => options.concurrency = concurrency,
+                _ => usage(),
+            },
+            "--time-limit" => match value.parse().map(Duration::try_from_secs_f64) {
+                Ok(Ok(time_limit)) => options.time_limit = time_limit,
+                _ => usage(),
+            },
+            "--settle" => match value.parse().map(Duration::try_from_secs_f64) {
+                Ok(Ok(settle)) => options.settle = settle,
+                _ => usage(),
+            },
+            "--seed" => options.seed = value.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+
+    options
+}
+
+/// The time between operations at `rate` per second, at least 1ns. `None`
+/// unless the rate is finite and positive.
+fn period(rate: f64) -> Option<Duration> {
+    if !rate.is_finite() || rate <= 0.0 {
+        return None;
+    }
+
+    let period = Duration::try_from_secs_f64(1.0 / rate).ok()?;
+    Some(period.max(Duration::from_nanos(1)))
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: loadgen <broadcast|g-counter|kafka|unique-ids> [--port PORT] [--rate OPS_PER_SEC]"
+    );
+    eprintln!("               [--concurrency N] [--time-limit SECS] [--settle SECS] [--seed N]");
+    process::exit(2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_become_periods_of_at_least_a_nanosecond() {
+        assert_eq!(period(10.0), Some(Duration::from_millis(100)));
+        assert_eq!(period(1e300), Some(Duration::from_nanos(1)));
+        for rate in [0.0, -1.0, f64::INFINITY, f64::NAN, 1e-300] {
+            assert_eq!(period(rate), None, "{rate}");
+        }
+    }
+}
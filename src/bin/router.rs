@@ -0,0 +1,646 @@
+//! Runs node binaries as local processes and routes messages between them, so
+//! they can run without Maelstrom.
+//!
+//! ```text
+//! router [--nodes N] [--port PORT] [--topology grid|line|tree|total|none]
+//!        [--faults RULES] <binary> [args...]
+//! ```
+//!
+//! Nodes are named `n1` to `nN` and get `init`, then `topology`, a grid
+//! unless another one or `none` is asked for. Nodes that do not handle
+//! `topology` answer it with an error, which is fine. Clients connect over TCP
+//! and exchange Maelstrom messages as JSON lines; replies go back to the
+//! connection that last sent from their `dest`.
+//! `seq-kv`, `lin-kv` and `lww-kv` are answered in memory, and requests to
+//! `router` with `{"type": "stats"}` get the node ids and counts of the
+//! messages routed so far.
+//!
+//! `--faults` hands every node the same fault rules (see
+//! [`actors::Rule::parse_all`]); `{"type": "set_faults", "rules": "..."}`
+//! from a client switches a node's rules while it runs. Nodes draw faults
+//! with the seed in `GLOMERS_FAULT_SEED` if it is set.
+
+use gossip_glomers::{actors, maelstrom_protocol, sim};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    process::{self, Stdio},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    process::{Child, ChildStdin, Command},
+    sync::mpsc,
+};
+
+/// The id clients send router requests to.
+const ROUTER: &str = "router";
+
+/// How long nodes get to answer `init` and `topology`.
+const SETUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long nodes get to exit once their input is closed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+enum Topology {
+    Grid,
+    Line,
+    Tree,
+    Total,
+}
+
+struct Options {
+    nodes: usize,
+    port: u16,
+    topology: Option<Topology>,
+    faults: Option<String>,
+    command: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum Setup {
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum RouterPayload {
+    Stats,
+    StatsOk {
+        node_ids: Vec<String>,
+        /// Messages from one node to another.
+        node_messages: usize,
+        /// Messages between nodes and services, both ways.
+        service_messages: usize,
+        /// Messages between nodes and clients, both ways.
+        client_messages: usize,
+    },
+}
+
+impl maelstrom_protocol::Payload for RouterPayload {}
+
+enum Event {
+    Node(String),
+    Client(usize, String),
+    Connected(usize, mpsc::UnboundedSender<String>),
+    Disconnected(usize),
+}
+
+#[derive(Default)]
+struct Stats {
+    node_messages: usize,
+    service_messages: usize,
+    client_messages: usize,
+}
+
+struct Router {
+    inputs: HashMap<String, ChildStdin>,
+    services: HashMap<String, sim::KvStore>,
+    connections: HashMap<usize, mpsc::UnboundedSender<String>>,
+    /// The connection each client last sent from.
+    clients: HashMap<String, usize>,
+    /// Replies to setup messages, which come from [`sim::INIT_CLIENT`].
+    setup_replies: usize,
+    stats: Stats,
+}
+
+#[tokio::main]
+async fn main() {
+    let options = parse_args();
+    let node_ids: Vec<String> = (1..=options.nodes).map(|i| format!("n{i}")).collect();
+    let (events_tx, mut events) = mpsc::unbounded_channel();
+
+    let mut children = Vec::new();
+    let mut inputs = HashMap::new();
+    for node_id in &node_ids {
+        let (child, input) = spawn_node(&options, node_id, events_tx.clone());
+        children.push(child);
+        inputs.insert(node_id.clone(), input);
+    }
+
+    let mut router = Router {
+        inputs,
+        services: sim::KvStore::maelstrom(0),
+        connections: HashMap::new(),
+        clients: HashMap::new(),
+        setup_replies: 0,
+        stats: Stats::default(),
+    };
+
+    let mut setup: Vec<_> = node_ids
+        .iter()
+        .map(|node_id| {
+            serde_json::to_value(maelstrom_protocol::Handshake::Init(
+                maelstrom_protocol::InitPayload {
+                    node_id: node_id.clone(),
+                    node_ids: node_ids.clone(),
+                },
+            ))
+            .expect("could not serialize init")
+        })
+        .collect();
+    if let Some(topology) = options.topology {
+        let topology = make_topology(topology, &node_ids);
+        setup.extend(node_ids.iter().map(|_| {
+            serde_json::to_value(Setup::Topology {
+                topology: topology.clone(),
+            })
+            .expect("could not serialize topology")
+        }));
+    }
+
+    // init everywhere first, then the topology
+    for (i, payload) in setup.into_iter().enumerate() {
+        let node_id = &node_ids[i % node_ids.len()];
+        let mut message = maelstrom_protocol::Message::new(
+            sim::INIT_CLIENT.to_string(),
+            node_id.clone(),
+            payload,
+        );
+        message.body.id = Some(i + 1);
+        router
+            .route(serde_json::to_string(&message).expect("could not serialize message"))
+            .await;
+
+        if (i + 1) % node_ids.len() == 0 {
+            router.await_setup(&mut events, i + 1).await;
+        }
+    }
+
+    let listener = match TcpListener::bind(("127.0.0.1", options.port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("could not listen on port {}: {e}", options.port);
+            process::exit(1);
+        }
+    };
+    eprintln!(
+        "{} nodes ready, clients can connect to 127.0.0.1:{}",
+        options.nodes, options.port
+    );
+    tokio::spawn(accept(listener, events_tx));
+
+    loop {
+        tokio::select! {
+            Some(event) = events.recv() => router.handle(event).await,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    // closing their input asks the nodes to stop
+    drop(router);
+    for mut child in children {
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, child.wait())
+            .await
+            .is_err()
+        {
+            let _ = child.kill().await;
+        }
+    }
+}
+
+impl Router {
+    async fn handle(&mut self, event: Event) {
+        match event {
+            Event::Node(line) => self.route(line).await,
+            Event::Client(connection, line) => {
+                if let Ok(envelope) = serde_json::from_str::<maelstrom_protocol::Envelope>(&line) {
+                    self.clients.insert(envelope.src, connection);
+                }
+                self.route(line).await;
+            }
+            Event::Connected(connection, output) => {
+                self.connections.insert(connection, output);
+            }
+            Event::Disconnected(connection) => {
+                self.connections.remove(&connection);
+                self.clients.retain(|_, c| *c != connection);
+            }
+        }
+    }
+
+    /// Handles events until `count` setup messages have been answered.
+    async fn await_setup(&mut self, events: &mut mpsc::UnboundedReceiver<Event>, count: usize) {
+        let answered = tokio::time::timeout(SETUP_TIMEOUT, async {
+            while self.setup_replies < count {
+                match events.recv().await {
+                    Some(event) => self.handle(event).await,
+                    None => return,
+                }
+            }
+        });
+
+        if answered.await.is_err() || self.setup_replies < count {
+            eprintln!("nodes did not answer init and topology");
+            process::exit(1);
+        }
+    }
+
+    async fn route(&mut self, line: String) {
+        let envelope = match serde_json::from_str::<maelstrom_protocol::Envelope>(&line) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                eprintln!("malformed message: {e}: {line}");
+                return;
+            }
+        };
+
+        let from_node = self.inputs.contains_key(&envelope.src);
+        if self.inputs.contains_key(&envelope.dst) {
+            match from_node {
+                true => self.stats.node_messages += 1,
+                false if self.services.contains_key(&envelope.src) => {
+                    self.stats.service_messages += 1
+                }
+                false if envelope.src != sim::INIT_CLIENT => self.stats.client_messages += 1,
+                false => {}
+            }
+            self.deliver(&envelope.dst, line).await;
+        } else if envelope.dst == sim::INIT_CLIENT {
+            self.setup_replies += 1;
+        } else if self.services.contains_key(&envelope.dst) {
+            self.stats.service_messages += 1;
+            self.serve(envelope).await;
+        } else if envelope.dst == ROUTER {
+            self.answer(envelope).await;
+        } else {
+            if from_node {
+                self.stats.client_messages += 1;
+            }
+            let connection = self.clients.get(&envelope.dst);
+            match connection.and_then(|c| self.connections.get(c)) {
+                Some(output) => {
+                    let _ = output.send(line);
+                }
+                None => eprintln!("dropping message to unknown {}", envelope.dst),
+            }
+        }
+    }
+
+    async fn deliver(&mut self, node_id: &str, line: String) {
+        let input = self.inputs.get_mut(node_id).expect("node is running");
+        let written = async {
+            input.write_all(line.as_bytes()).await?;
+            input.write_all(b"\n").await?;
+            input.flush().await
+        };
+        if let Err(e) = written.await {
+            eprintln!("could not deliver to {node_id}: {e}");
+        }
+    }
+
+    /// Answers a request to a service.
+    async fn serve(&mut self, envelope: maelstrom_protocol::Envelope) {
+        let service = self
+            .services
+            .get_mut(&envelope.dst)
+            .expect("service is registered");
+
+        let reply = match envelope.parse() {
+            Ok(request) => service.handle(&request),
+            Err(e) => {
+                eprintln!("malformed service request: {e}");
+                return;
+            }
+        };
+
+        if let Some(reply) = reply {
+            Box::pin(self.route(serde_json::to_string(&reply).expect("could not serialize reply")))
+                .await;
+        }
+    }
+
+    /// Answers a request to the router itself.
+    async fn answer(&mut self, envelope: maelstrom_protocol::Envelope) {
+        let request = match envelope.parse::<RouterPayload>() {
+            Ok(request) => request,
+            Err(e) => {
+                eprintln!("malformed router request: {e}");
+                return;
+            }
+        };
+
+        let mut node_ids: Vec<_> = self.inputs.keys().cloned().collect();
+        node_ids.sort_by_key(|node_id| (node_id.len(), node_id.clone()));
+
+        let reply = match request.body.payload {
+            RouterPayload::Stats => request.make_response(RouterPayload::StatsOk {
+                node_ids,
+                node_messages: self.stats.node_messages,
+                service_messages: self.stats.service_messages,
+                client_messages: self.stats.client_messages,
+            }),
+            RouterPayload::StatsOk { .. } => return,
+        };
+
+        let line = serde_json::to_string(&reply).expect("could not serialize reply");
+        let connection = self.clients.get(&reply.dst);
+        if let Some(output) = connection.and_then(|c| self.connections.get(c)) {
+            let _ = output.send(line);
+        }
+    }
+}
+
+fn spawn_node(
+    options: &Options,
+    node_id: &str,
+    events: mpsc::UnboundedSender<Event>,
+) -> (Child, ChildStdin) {
+    let command = &options.command;
+    let mut node = Command::new(&command[0]);
+    node.env(actors::FAULT_CONTROL_ENV, "1");
+    if let Some(faults) = &options.faults {
+        node.env(actors::FAULTS_ENV, faults);
+    }
+
+    let mut child = match node
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("could not start {}: {e}", command[0]);
+            process::exit(1);
+        }
+    };
+
+    let input = child.stdin.take().expect("stdin is piped");
+    let output = child.stdout.take().expect("stdout is piped");
+    let node_id = node_id.to_string();
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(output).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if events.send(Event::Node(line)).is_err() {
+                return;
+            }
+        }
+        eprintln!("{node_id} closed its output");
+    });
+
+    (child, input)
+}
+
+async fn accept(listener: TcpListener, events: mpsc::UnboundedSender<Event>) {
+    let mut next_connection = 0;
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("could not accept a client: {e}");
+                continue;
+            }
+        };
+
+        next_connection += 1;
+        tokio::spawn(serve_client(next_connection, stream, events.clone()));
+    }
+}
+
+async fn serve_client(connection: usize, stream: TcpStream, events: mpsc::UnboundedSender<Event>) {
+    let (reader, mut writer) = stream.into_split();
+    let (output_tx, mut output) = mpsc::unbounded_channel::<String>();
+    if events
+        .send(Event::Connected(connection, output_tx))
+        .is_err()
+    {
+        return;
+    }
+
+    tokio::spawn(async move {
+        while let Some(line) = output.recv().await {
+            let written = async {
+                writer.write_all(line.as_bytes()).await?;
+                writer.write_all(b"\n").await
+            };
+            if written.await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if events.send(Event::Client(connection, line)).is_err() {
+            return;
+        }
+    }
+    let _ = events.send(Event::Disconnected(connection));
+}
+
+/// Who each node talks to, as Maelstrom lays it out.
+fn make_topology(topology: Topology, node_ids: &[String]) -> HashMap<String, Vec<String>> {
+    let n = node_ids.len();
+    let side = (1..=n).find(|side| side * side >= n).unwrap_or(1);
+
+    let neighbours = |i: usize| -> Vec<usize> {
+        match topology {
+            Topology::Grid => {
+                let (row, column) = (i / side, i % side);
+                let mut neighbours = Vec::new();
+                if row > 0 {
+                    neighbours.push(i - side);
+                }
+                if column > 0 {
+                    neighbours.push(i - 1);
+                }
+                if column + 1 < side {
+                    neighbours.push(i + 1);
+                }
+                neighbours.push(i + side);
+                neighbours
+            }
+            Topology::Line => vec![i.wrapping_sub(1), i + 1],
+            Topology::Tree => vec![i.wrapping_sub(1) / 2, 2 * i + 1, 2 * i + 2],
+            Topology::Total => (0..n).collect(),
+        }
+    };
+
+    node_ids
+        .iter()
+        .enumerate()
+        .map(|(i, node_id)| {
+            let neighbours = neighbours(i)
+                .into_iter()
+                .filter(|j| *j != i && *j < n)
+                .map(|j| node_ids[j].clone())
+                .collect();
+            (node_id.clone(), neighbours)
+        })
+        .collect()
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        nodes: 5,
+        port: 7000,
+        topology: Some(Topology::Grid),
+        faults: None,
+        command: Vec::new(),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--nodes" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => options.nodes = n,
+                _ => usage(),
+            },
+            "--port" => match args.next().and_then(|p| p.parse().ok()) {
+                Some(port) => options.port = port,
+                None => usage(),
+            },
+            "--topology" => match args.next().as_deref() {
+                Some("grid") => options.topology = Some(Topology::Grid),
+                Some("line") => options.topology = Some(Topology::Line),
+                Some("tree") => options.topology = Some(Topology::Tree),
+                Some("total") => options.topology = Some(Topology::Total),
+                Some("none") => options.topology = None,
+                _ => usage(),
+            },
+            "--faults" => match args.next() {
+                Some(rules) => match actors::Rule::parse_all(&rules) {
+                    Ok(_) => options.faults = Some(rules),
+                    Err(e) => {
+                        eprintln!("{e}");
+                        process::exit(2);
+                    }
+                },
+                None => usage(),
+            },
+            _ if arg.starts_with("--") => usage(),
+            _ => {
+                options.command.push(arg);
+                options.command.extend(args.by_ref());
+            }
+        }
+    }
+
+    if options.command.is_empty() {
+        usage();
+    }
+    options
+}
+
+fn usage() -> ! {
+    eprintln!("usage: router [--nodes N] [--port PORT] [--topology grid|line|tree|total|none]");
+    eprintln!("              [--faults RULES] <binary> [args...]");
+    eprintln!();
+    eprintln!("  --nodes N         run N nodes, n1 to nN (default 5)");
+    eprintln!("  --port PORT       listen for clients on PORT (default 7000)");
+    eprintln!("  --topology T      send each node topology T after init (default grid)");
+    eprintln!("  --faults RULES    apply fault rules to every node's output");
+    process::exit(2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topology(topology: Topology, n: usize) -> Vec<(String, Vec<String>)> {
+        let node_ids: Vec<_> = (1..=n).map(|i| format!("n{i}")).collect();
+        let topology = make_topology(topology, &node_ids);
+        node_ids
+            .into_iter()
+            .map(|node_id| {
+                let neighbours = topology[&node_id].clone();
+                (node_id, neighbours)
+            })
+            .collect()
+    }
+
+    fn expected(neighbours: &[(&str, &[&str])]) -> Vec<(String, Vec<String>)> {
+        neighbours
+            .iter()
+            .map(|(node_id, neighbours)| {
+                let neighbours = neighbours.iter().map(|n| n.to_string()).collect();
+                (node_id.to_string(), neighbours)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn grids() {
+        assert_eq!(topology(Topology::Grid, 1), expected(&[("n1", &[])]));
+
+        // one row
+        assert_eq!(
+            topology(Topology::Grid, 2),
+            expected(&[("n1", &["n2"]), ("n2", &["n1"])])
+        );
+
+        // three wide, with a short second row
+        assert_eq!(
+            topology(Topology::Grid, 5),
+            expected(&[
+                ("n1", &["n2", "n4"]),
+                ("n2", &["n1", "n3", "n5"]),
+                ("n3", &["n2"]),
+                ("n4", &["n1", "n5"]),
+                ("n5", &["n2", "n4"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn lines_and_trees_stop_at_the_edges() {
+        assert_eq!(topology(Topology::Line, 1), expected(&[("n1", &[])]));
+        assert_eq!(
+            topology(Topology::Line, 3),
+            expected(&[("n1", &["n2"]), ("n2", &["n1", "n3"]), ("n3", &["n2"])])
+        );
+        assert_eq!(
+            topology(Topology::Tree, 4),
+            expected(&[
+                ("n1", &["n2", "n3"]),
+                ("n2", &["n1", "n4"]),
+                ("n3", &["n1"]),
+                ("n4", &["n2"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn every_topology_is_symmetric_and_connected() {
+        for kind in [
+            Topology::Grid,
+            Topology::Line,
+            Topology::Tree,
+            Topology::Total,
+        ] {
+            for n in 1..=12 {
+                let node_ids: Vec<_> = (1..=n).map(|i| format!("n{i}")).collect();
+                let topology = make_topology(kind, &node_ids);
+
+                for (node_id, neighbours) in &topology {
+                    assert!(!neighbours.contains(node_id), "{kind:?} {n}");
+                    for neighbour in neighbours {
+                        assert!(topology[neighbour].contains(node_id), "{kind:?} {n}");
+                    }
+                }
+
+                let mut reached = vec![node_ids[0].clone()];
+                let mut i = 0;
+                while let Some(node_id) = reached.get(i).cloned() {
+                    for neighbour in &topology[&node_id] {
+                        if !reached.contains(neighbour) {
+                            reached.push(neighbour.clone());
+                        }
+                    }
+                    i += 1;
+                }
+                assert_eq!(reached.len(), n, "{kind:?} {n}");
+            }
+        }
+    }
+}
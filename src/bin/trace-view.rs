@@ -0,0 +1,108 @@
+//! Draws the message flow recorded in node traces.
+//!
+//! ```text
+//! trace-view [--mermaid | --graphviz | --matrix] <trace file or dir>...
+//! ```
+//!
+//! Directories are searched for `*.jsonl` traces, like the ones written under
+//! `GLOMERS_TRACE_DIR`.
+
+use gossip_glomers::trace;
+use std::{path::PathBuf, process};
+
+enum Format {
+    Mermaid,
+    Graphviz,
+    Matrix,
+}
+
+fn main() {
+    let mut format = Format::Mermaid;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--mermaid" => format = Format::Mermaid,
+            "--graphviz" => format = Format::Graphviz,
+            "--matrix" => format = Format::Matrix,
+            _ if arg.starts_with("--") => usage(),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.is_empty() {
+        usage();
+    }
+
+    let mut traces = Vec::new();
+    for path in trace_files(paths) {
+        match trace::read(&path) {
+            Ok(entries) => traces.push(entries),
+            Err(e) => {
+                eprintln!("failed to read {}: {e}", path.display());
+                process::exit(1);
+            }
+        }
+    }
+
+    let flows = trace::flows(traces.iter().map(Vec::as_slice));
+    match format {
+        Format::Mermaid => print!("{}", trace::mermaid(&flows)),
+        Format::Graphviz => print!("{}", trace::graphviz(&flows)),
+        Format::Matrix => print!("{}", trace::TrafficMatrix::new(&flows)),
+    }
+}
+
+fn trace_files(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path);
+            continue;
+        }
+
+        let entries = match std::fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("failed to read {}: {e}", path.display());
+                process::exit(1);
+            }
+        };
+        let mut traces: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+            .collect();
+        traces.sort();
+        files.extend(traces);
+    }
+    files
+}
+
+fn usage() -> ! {
+    eprintln!("usage: trace-view [--mermaid | --graphviz | --matrix] <trace file or dir>...");
+    process::exit(2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directories_are_searched_for_traces() {
+        let dir = std::env::temp_dir().join(format!("glomers-trace-view-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["n1.jsonl", "n0.jsonl", "notes.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let files = trace_files(vec![PathBuf::from("extra.jsonl"), dir.clone()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            files,
+            [
+                PathBuf::from("extra.jsonl"),
+                dir.join("n0.jsonl"),
+                dir.join("n1.jsonl")
+            ]
+        );
+    }
+}
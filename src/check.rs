@@ -0,0 +1,11 @@
+//! Offline checkers for histories and traces.
+
+mod broadcast;
+mod counter;
+mod kafka;
+mod linearizable;
+
+pub use broadcast::*;
+pub use counter::*;
+pub use kafka::*;
+pub use linearizable::*;
@@ -0,0 +1,246 @@
+use crate::history::{ClientOp, EventKind, History};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BroadcastOp {
+    Broadcast {
+        node: String,
+        message: usize,
+    },
+    Read {
+        node: String,
+        messages: Option<HashSet<usize>>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum BroadcastPayload {
+    Broadcast {
+        message: usize,
+    },
+    Read,
+    ReadOk {
+        messages: HashSet<usize>,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+impl ClientOp for BroadcastOp {
+    type Payload = BroadcastPayload;
+
+    fn invoked(node: &str, request: &BroadcastPayload) -> Option<Self> {
+        match request {
+            BroadcastPayload::Broadcast { message } => Some(Self::Broadcast {
+                node: node.to_string(),
+                message: *message,
+            }),
+            BroadcastPayload::Read => Some(Self::Read {
+                node: node.to_string(),
+                messages: None,
+            }),
+            _ => None,
+        }
+    }
+
+    fn completed(&self, reply: &BroadcastPayload) -> Self {
+        match (self, reply) {
+            (Self::Read { node, .. }, BroadcastPayload::ReadOk { messages }) => Self::Read {
+                node: node.clone(),
+                messages: Some(messages.clone()),
+            },
+            _ => self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastReport {
+    /// Operations that succeeded. A history without any proves nothing.
+    pub checked: usize,
+    /// Messages whose broadcast was acknowledged.
+    pub acknowledged: BTreeSet<usize>,
+    /// Acknowledged messages missing from each node's last read.
+    pub lost: BTreeMap<String, BTreeSet<usize>>,
+    /// Messages some read returned that nobody tried to broadcast.
+    pub unexpected: BTreeSet<usize>,
+    /// Nodes that were sent broadcasts but never answered a read.
+    pub unread: BTreeSet<String>,
+}
+
+impl BroadcastReport {
+    pub fn is_valid(&self) -> bool {
+        self.checked > 0
+            && self.lost.is_empty()
+            && self.unexpected.is_empty()
+            && self.unread.is_empty()
+    }
+}
+
+impl std::fmt::Display for BroadcastReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.checked == 0 {
+            writeln!(f, "no operations to check")?;
+        }
+        writeln!(f, "{} acknowledged broadcasts", self.acknowledged.len())?;
+        for (node, lost) in &self.lost {
+            writeln!(f, "{node} never read {lost:?}")?;
+        }
+        if !self.unexpected.is_empty() {
+            writeln!(f, "read but never broadcast: {:?}", self.unexpected)?;
+        }
+        if !self.unread.is_empty() {
+            writeln!(f, "never read from: {:?}", self.unread)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that every acknowledged broadcast made it into the last successful
+/// read on every node, and that reads only return broadcast messages. The
+/// last reads should come once the cluster has settled, like Maelstrom's
+/// final reads.
+pub fn check_broadcast(history: &History<BroadcastOp>) -> BroadcastReport {
+    let mut report = BroadcastReport::default();
+    let mut attempted = HashSet::new();
+    let mut nodes = BTreeSet::new();
+    let mut last_reads: BTreeMap<String, (usize, HashSet<usize>)> = BTreeMap::new();
+
+    for operation in history.operations() {
+        if operation.kind == EventKind::Ok {
+            report.checked += 1;
+        }
+        match operation.op {
+            BroadcastOp::Broadcast { node, message } => {
+                attempted.insert(message);
+                nodes.insert(node);
+                if operation.kind == EventKind::Ok {
+                    report.acknowledged.insert(message);
+                }
+            }
+            BroadcastOp::Read {
+                node,
+                messages: Some(messages),
+            } => {
+                let ret = operation.ret.unwrap_or_default();
+                report.unexpected.extend(messages.iter().copied());
+                nodes.insert(node.clone());
+
+                match last_reads.get(&node) {
+                    Some((at, _)) if *at > ret => {}
+                    _ => {
+                        last_reads.insert(node, (ret, messages));
+                    }
+                }
+            }
+            BroadcastOp::Read { .. } => {}
+        }
+    }
+
+    report.unexpected.retain(|m| !attempted.contains(m));
+
+    for node in nodes {
+        let Some((_, messages)) = last_reads.get(&node) else {
+            report.unread.insert(node);
+            continue;
+        };
+
+        let lost: BTreeSet<_> = report
+            .acknowledged
+            .iter()
+            .filter(|m| !messages.contains(*m))
+            .copied()
+            .collect();
+        if !lost.is_empty() {
+            report.lost.insert(node, lost);
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn broadcast(node: &str, message: usize) -> BroadcastOp {
+        BroadcastOp::Broadcast {
+            node: node.to_string(),
+            message,
+        }
+    }
+
+    fn read(node: &str, messages: &[usize]) -> BroadcastOp {
+        BroadcastOp::Read {
+            node: node.to_string(),
+            messages: Some(messages.iter().copied().collect()),
+        }
+    }
+
+    #[test]
+    fn every_node_read_every_broadcast() {
+        let history = History::sequential([
+            (EventKind::Ok, broadcast("n1", 1)),
+            (EventKind::Ok, broadcast("n2", 2)),
+            // may or may not have happened, so it need not be read
+            (EventKind::Info, broadcast("n2", 3)),
+            (EventKind::Ok, read("n1", &[1, 2, 3])),
+            (EventKind::Ok, read("n2", &[1, 2])),
+        ]);
+
+        let report = check_broadcast(&history);
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.acknowledged, BTreeSet::from([1, 2]));
+    }
+
+    #[test]
+    fn only_the_last_read_counts() {
+        let history = History::sequential([
+            (EventKind::Ok, read("n1", &[])),
+            (EventKind::Ok, broadcast("n1", 1)),
+            (EventKind::Ok, read("n1", &[1])),
+            (EventKind::Ok, broadcast("n1", 2)),
+            (EventKind::Ok, read("n1", &[1])),
+        ]);
+
+        let report = check_broadcast(&history);
+        assert_eq!(
+            report.lost,
+            BTreeMap::from([("n1".to_string(), BTreeSet::from([2]))])
+        );
+    }
+
+    #[test]
+    fn lost_unexpected_and_unread() {
+        let history = History::sequential([
+            (EventKind::Ok, broadcast("n1", 1)),
+            (EventKind::Ok, broadcast("n3", 2)),
+            (EventKind::Fail, broadcast("n1", 4)),
+            (EventKind::Ok, read("n1", &[1, 2, 4, 9])),
+            (EventKind::Ok, read("n2", &[1])),
+        ]);
+
+        let report = check_broadcast(&history);
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.lost,
+            BTreeMap::from([("n2".to_string(), BTreeSet::from([2]))])
+        );
+        assert_eq!(report.unexpected, BTreeSet::from([9]));
+        assert_eq!(report.unread, BTreeSet::from(["n3".to_string()]));
+    }
+
+    #[test]
+    fn empty_history_is_not_valid() {
+        // only unanswered requests, as from a trace that could not be read
+        let mut history = History::default();
+        history.push("a", EventKind::Invoke, broadcast("n1", 1));
+
+        let report = check_broadcast(&history);
+        assert!(!report.is_valid());
+        assert!(report.to_string().contains("no operations"));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::history::{ClientOp, EventKind, History};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CounterOp {
+    Add { node: String, delta: usize },
+    Read { node: String, value: Option<usize> },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum CounterPayload {
+    Add {
+        delta: usize,
+    },
+    Read,
+    ReadOk {
+        value: usize,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+impl ClientOp for CounterOp {
+    type Payload = CounterPayload;
+
+    fn invoked(node: &str, request: &CounterPayload) -> Option<Self> {
+        match request {
+            CounterPayload::Add { delta } => Some(Self::Add {
+                node: node.to_string(),
+                delta: *delta,
+            }),
+            CounterPayload::Read => Some(Self::Read {
+                node: node.to_string(),
+                value: None,
+            }),
+            _ => None,
+        }
+    }
+
+    fn completed(&self, reply: &CounterPayload) -> Self {
+        match (self, reply) {
+            (Self::Read { node, .. }, CounterPayload::ReadOk { value }) => Self::Read {
+                node: node.clone(),
+                value: Some(*value),
+            },
+            _ => self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CounterReport {
+    /// The sum of acknowledged adds.
+    pub acknowledged: usize,
+    /// The sum of adds that may or may not have happened.
+    pub indeterminate: usize,
+    /// The last value read on each node.
+    pub final_reads: BTreeMap<String, usize>,
+    /// Final reads outside `acknowledged..=acknowledged + indeterminate`.
+    pub wrong: BTreeMap<String, usize>,
+}
+
+impl CounterReport {
+    pub fn is_valid(&self) -> bool {
+        self.wrong.is_empty() && !self.final_reads.is_empty()
+    }
+}
+
+impl std::fmt::Display for CounterReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "expected {}..={}",
+            self.acknowledged,
+            self.acknowledged + self.indeterminate
+        )?;
+        for (node, value) in &self.final_reads {
+            let verdict = match self.wrong.contains_key(node) {
+                true => "wrong",
+                false => "ok",
+            };
+            writeln!(f, "{node} read {value} ({verdict})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the last read on every node equals the sum of acknowledged
+/// adds, allowing for adds whose outcome is unknown.
+pub fn check_counter(history: &History<CounterOp>) -> CounterReport {
+    let mut report = CounterReport::default();
+    let mut last_reads: BTreeMap<String, usize> = BTreeMap::new();
+
+    for operation in history.operations() {
+        match operation.op {
+            CounterOp::Add { delta, .. } => match operation.kind {
+                EventKind::Ok => report.acknowledged += delta,
+                EventKind::Fail => {}
+                EventKind::Invoke | EventKind::Info => report.indeterminate += delta,
+            },
+            CounterOp::Read {
+                node,
+                value: Some(value),
+            } => {
+                let ret = operation.ret.unwrap_or_default();
+                match last_reads.get(&node) {
+                    Some(at) if *at > ret => {}
+                    _ => {
+                        last_reads.insert(node.clone(), ret);
+                        report.final_reads.insert(node, value);
+                    }
+                }
+            }
+            CounterOp::Read { .. } => {}
+        }
+    }
+
+    let expected = report.acknowledged..=report.acknowledged + report.indeterminate;
+    report.wrong = report
+        .final_reads
+        .iter()
+        .filter(|(_, value)| !expected.contains(*value))
+        .map(|(node, value)| (node.clone(), *value))
+        .collect();
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(delta: usize) -> CounterOp {
+        CounterOp::Add {
+            node: "n1".to_string(),
+            delta,
+        }
+    }
+
+    fn read(node: &str, value: usize) -> CounterOp {
+        CounterOp::Read {
+            node: node.to_string(),
+            value: Some(value),
+        }
+    }
+
+    #[test]
+    fn final_reads_allow_for_unknown_adds() {
+        let history = History::sequential([
+            (EventKind::Ok, add(1)),
+            (EventKind::Ok, read("n1", 100)),
+            (EventKind::Ok, add(2)),
+            (EventKind::Info, add(4)),
+            (EventKind::Fail, add(8)),
+            (EventKind::Ok, read("n1", 3)),
+            (EventKind::Ok, read("n2", 7)),
+        ]);
+
+        let report = check_counter(&history);
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.acknowledged, 3);
+        assert_eq!(report.indeterminate, 4);
+        assert_eq!(report.final_reads["n1"], 3);
+    }
+
+    #[test]
+    fn wrong_final_reads() {
+        let history = History::sequential([
+            (EventKind::Ok, add(1)),
+            (EventKind::Ok, add(2)),
+            (EventKind::Fail, add(8)),
+            (EventKind::Ok, read("n1", 2)),
+            (EventKind::Ok, read("n2", 11)),
+            (EventKind::Ok, read("n3", 3)),
+        ]);
+
+        let report = check_counter(&history);
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.wrong,
+            BTreeMap::from([("n1".to_string(), 2), ("n2".to_string(), 11)])
+        );
+    }
+
+    #[test]
+    fn no_reads_is_not_valid() {
+        let history = History::sequential([(EventKind::Ok, add(1))]);
+        assert!(!check_counter(&history).is_valid());
+    }
+}
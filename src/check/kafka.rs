@@ -0,0 +1,528 @@
+use crate::history::{ClientOp, EventKind, History, Operation};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// A message in a log: `(offset, msg)`.
+pub type Msg = (usize, usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KafkaOp {
+    Send {
+        key: String,
+        msg: usize,
+        offset: Option<usize>,
+    },
+    Poll {
+        offsets: HashMap<String, usize>,
+        msgs: Option<HashMap<String, Vec<Msg>>>,
+    },
+    CommitOffsets {
+        offsets: HashMap<String, usize>,
+    },
+    ListCommittedOffsets {
+        keys: HashSet<String>,
+        offsets: Option<HashMap<String, usize>>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum KafkaPayload {
+    Send {
+        key: String,
+        msg: usize,
+    },
+    SendOk {
+        offset: usize,
+    },
+    Poll {
+        offsets: HashMap<String, usize>,
+    },
+    PollOk {
+        msgs: HashMap<String, Vec<Msg>>,
+    },
+    CommitOffsets {
+        offsets: HashMap<String, usize>,
+    },
+    ListCommittedOffsets {
+        keys: HashSet<String>,
+    },
+    ListCommittedOffsetsOk {
+        offsets: HashMap<String, usize>,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+impl ClientOp for KafkaOp {
+    type Payload = KafkaPayload;
+
+    fn invoked(_node: &str, request: &KafkaPayload) -> Option<Self> {
+        match request {
+            KafkaPayload::Send { key, msg } => Some(Self::Send {
+                key: key.clone(),
+                msg: *msg,
+                offset: None,
+            }),
+            KafkaPayload::Poll { offsets } => Some(Self::Poll {
+                offsets: offsets.clone(),
+                msgs: None,
+            }),
+            KafkaPayload::CommitOffsets { offsets } => Some(Self::CommitOffsets {
+                offsets: offsets.clone(),
+            }),
+            KafkaPayload::ListCommittedOffsets { keys } => Some(Self::ListCommittedOffsets {
+                keys: keys.clone(),
+                offsets: None,
+            }),
+            _ => None,
+        }
+    }
+
+    fn completed(&self, reply: &KafkaPayload) -> Self {
+        match (self, reply) {
+            (Self::Send { key, msg, .. }, KafkaPayload::SendOk { offset }) => Self::Send {
+                key: key.clone(),
+                msg: *msg,
+                offset: Some(*offset),
+            },
+            (Self::Poll { offsets, .. }, KafkaPayload::PollOk { msgs }) => Self::Poll {
+                offsets: offsets.clone(),
+                msgs: Some(msgs.clone()),
+            },
+            (
+                Self::ListCommittedOffsets { keys, .. },
+                KafkaPayload::ListCommittedOffsetsOk { offsets },
+            ) => Self::ListCommittedOffsets {
+                keys: keys.clone(),
+                offsets: Some(offsets.clone()),
+            },
+            _ => self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct KafkaReport {
+    /// Operations that succeeded. A history without any proves nothing.
+    pub checked: usize,
+    /// Sends acknowledged with a lower offset than a send that had already
+    /// been acknowledged on the same key: `(key, offset, earlier offset)`.
+    pub nonmonotonic: Vec<(String, usize, usize)>,
+    /// Offsets that held different messages in different places.
+    pub inconsistent: BTreeSet<(String, usize)>,
+    /// Polls that returned a key's messages out of offset order.
+    pub unordered_polls: Vec<(String, Vec<usize>)>,
+    /// Acknowledged sends no poll returned, though polls got past them.
+    pub lost: BTreeSet<(String, usize)>,
+    /// Acknowledged offsets a poll stepped over, or that a poll resuming past
+    /// the committed offset left behind unread: `(key, offset)`.
+    pub skipped: BTreeSet<(String, usize)>,
+    /// Committed offsets listed lower than an acknowledged commit:
+    /// `(key, listed, committed)`.
+    pub committed_regressions: Vec<(String, usize, usize)>,
+}
+
+impl KafkaReport {
+    pub fn is_valid(&self) -> bool {
+        self.checked > 0
+            && self.nonmonotonic.is_empty()
+            && self.inconsistent.is_empty()
+            && self.unordered_polls.is_empty()
+            && self.lost.is_empty()
+            && self.skipped.is_empty()
+            && self.committed_regressions.is_empty()
+    }
+}
+
+impl std::fmt::Display for KafkaReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.checked == 0 {
+            writeln!(f, "no operations to check")?;
+        }
+        for (key, offset, earlier) in &self.nonmonotonic {
+            writeln!(f, "{key}: offset {offset} acknowledged after {earlier}")?;
+        }
+        for (key, offset) in &self.inconsistent {
+            writeln!(f, "{key}: offset {offset} held different messages")?;
+        }
+        for (key, offsets) in &self.unordered_polls {
+            writeln!(f, "{key}: poll returned offsets {offsets:?}")?;
+        }
+        for (key, offset) in &self.lost {
+            writeln!(f, "{key}: offset {offset} was lost")?;
+        }
+        for (key, offset) in &self.skipped {
+            writeln!(f, "{key}: offset {offset} was skipped by a poll")?;
+        }
+        for (key, listed, committed) in &self.committed_regressions {
+            writeln!(f, "{key}: listed {listed} after committing {committed}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that offsets grow per key, that polls return every acknowledged
+/// message in order without gaps, that no poll skips past a committed offset,
+/// and that committed offsets never go back.
+pub fn check_kafka(history: &History<KafkaOp>) -> KafkaReport {
+    let mut report = KafkaReport::default();
+    let operations: Vec<_> = history
+        .operations()
+        .into_iter()
+        .filter(|operation| operation.kind == EventKind::Ok)
+        .collect();
+    report.checked = operations.len();
+
+    // acknowledged sends per key, as (ret, call, offset, msg)
+    let mut sends: BTreeMap<&str, Vec<(usize, usize, usize, usize)>> = BTreeMap::new();
+    for operation in &operations {
+        if let KafkaOp::Send {
+            key,
+            msg,
+            offset: Some(offset),
+        } = &operation.op
+        {
+            sends.entry(key).or_default().push((
+                operation.ret.unwrap_or_default(),
+                operation.call,
+                *offset,
+                *msg,
+            ));
+        }
+    }
+
+    // every message seen at an offset, to find inconsistencies
+    let mut seen: HashMap<(&str, usize), usize> = HashMap::new();
+
+    for (key, sends) in &mut sends {
+        sends.sort();
+
+        for (_, call, offset, msg) in sends.iter() {
+            let earlier = sends
+                .iter()
+                .filter(|(ret, ..)| ret < call)
+                .map(|(_, _, offset, _)| *offset)
+                .max();
+            if let Some(earlier) = earlier.filter(|earlier| earlier >= offset) {
+                report
+                    .nonmonotonic
+                    .push((key.to_string(), *offset, earlier));
+            }
+
+            if seen.insert((key, *offset), *msg).is_some_and(|m| m != *msg) {
+                report.inconsistent.insert((key.to_string(), *offset));
+            }
+        }
+    }
+
+    // when each offset was first returned by a poll
+    let mut polled: HashMap<(&str, usize), usize> = HashMap::new();
+    let mut highest_polled: HashMap<&str, usize> = HashMap::new();
+
+    for operation in &operations {
+        let KafkaOp::Poll {
+            offsets,
+            msgs: Some(msgs),
+        } = &operation.op
+        else {
+            continue;
+        };
+
+        for (key, msgs) in msgs {
+            let returned: Vec<usize> = msgs.iter().map(|(offset, _)| *offset).collect();
+            if returned.windows(2).any(|w| w[0] >= w[1]) {
+                report.unordered_polls.push((key.clone(), returned.clone()));
+            }
+
+            for (offset, msg) in msgs {
+                let ret = operation.ret.unwrap_or_default();
+                polled
+                    .entry((key, *offset))
+                    .and_modify(|first| *first = (*first).min(ret))
+                    .or_insert(ret);
+                if seen.insert((key, *offset), *msg).is_some_and(|m| m != *msg) {
+                    report.inconsistent.insert((key.clone(), *offset));
+                }
+            }
+
+            let Some(last) = returned.iter().max().copied() else {
+                continue;
+            };
+            let highest = highest_polled.entry(key).or_default();
+            *highest = (*highest).max(last);
+
+            // sends acknowledged before the poll started must all be there
+            let from = offsets.get(key).copied().unwrap_or_default();
+            for (ret, _, offset, _) in sends.get(key.as_str()).into_iter().flatten() {
+                if *ret < operation.call
+                    && (from..=last).contains(offset)
+                    && !returned.contains(offset)
+                {
+                    report.skipped.insert((key.clone(), *offset));
+                }
+            }
+        }
+    }
+
+    for (key, sends) in &sends {
+        let Some(highest) = highest_polled.get(key) else {
+            continue;
+        };
+        for (_, _, offset, _) in sends {
+            if offset < highest && !polled.contains_key(&(*key, *offset)) {
+                report.lost.insert((key.to_string(), *offset));
+            }
+        }
+    }
+
+    check_resumed_polls(&operations, &sends, &polled, &mut report);
+    check_commits(&operations, &mut report);
+
+    report
+}
+
+/// A poll starting past the committed offset must not leave acknowledged
+/// messages between the two that no earlier poll returned.
+fn check_resumed_polls(
+    operations: &[Operation<KafkaOp>],
+    sends: &BTreeMap<&str, Vec<(usize, usize, usize, usize)>>,
+    polled: &HashMap<(&str, usize), usize>,
+    report: &mut KafkaReport,
+) {
+    for poll in operations {
+        let KafkaOp::Poll { offsets, .. } = &poll.op else {
+            continue;
+        };
+
+        for (key, from) in offsets {
+            let committed = operations
+                .iter()
+                .filter(|commit| commit.ret.is_some_and(|ret| ret < poll.call))
+                .filter_map(|commit| match &commit.op {
+                    KafkaOp::CommitOffsets { offsets } => offsets.get(key).copied(),
+                    _ => None,
+                })
+                .max();
+            let Some(committed) = committed else {
+                continue;
+            };
+
+            for (ret, _, offset, _) in sends.get(key.as_str()).into_iter().flatten() {
+                let read = polled
+                    .get(&(key.as_str(), *offset))
+                    .is_some_and(|first| *first < poll.call);
+                if *ret < poll.call && (committed + 1..*from).contains(offset) && !read {
+                    report.skipped.insert((key.clone(), *offset));
+                }
+            }
+        }
+    }
+}
+
+/// Listed offsets must be at least what commits acknowledged before the list
+/// was invoked.
+fn check_commits(operations: &[Operation<KafkaOp>], report: &mut KafkaReport) {
+    for list in operations {
+        let KafkaOp::ListCommittedOffsets {
+            keys,
+            offsets: Some(listed),
+        } = &list.op
+        else {
+            continue;
+        };
+
+        let mut committed: HashMap<&str, usize> = HashMap::new();
+        for commit in operations {
+            let KafkaOp::CommitOffsets { offsets } = &commit.op else {
+                continue;
+            };
+            if commit.ret.is_some_and(|ret| ret < list.call) {
+                for (key, offset) in offsets.iter().filter(|(key, _)| keys.contains(*key)) {
+                    let entry = committed.entry(key).or_default();
+                    *entry = (*entry).max(*offset);
+                }
+            }
+        }
+
+        for (key, committed) in committed {
+            match listed.get(key) {
+                Some(listed) if *listed >= committed => {}
+                listed => report.committed_regressions.push((
+                    key.to_string(),
+                    listed.copied().unwrap_or_default(),
+                    committed,
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(msg: usize, offset: usize) -> KafkaOp {
+        KafkaOp::Send {
+            key: "k".to_string(),
+            msg,
+            offset: Some(offset),
+        }
+    }
+
+    fn poll(from: usize, msgs: &[Msg]) -> KafkaOp {
+        KafkaOp::Poll {
+            offsets: HashMap::from([("k".to_string(), from)]),
+            msgs: Some(HashMap::from([("k".to_string(), msgs.to_vec())])),
+        }
+    }
+
+    fn commit(offset: usize) -> KafkaOp {
+        KafkaOp::CommitOffsets {
+            offsets: HashMap::from([("k".to_string(), offset)]),
+        }
+    }
+
+    fn list(offset: usize) -> KafkaOp {
+        KafkaOp::ListCommittedOffsets {
+            keys: HashSet::from(["k".to_string()]),
+            offsets: Some(HashMap::from([("k".to_string(), offset)])),
+        }
+    }
+
+    fn ok(op: KafkaOp) -> (EventKind, KafkaOp) {
+        (EventKind::Ok, op)
+    }
+
+    #[test]
+    fn valid_log() {
+        let history = History::sequential(
+            [
+                send(10, 0),
+                send(11, 1),
+                send(12, 2),
+                poll(0, &[(0, 10), (1, 11), (2, 12)]),
+                poll(1, &[(1, 11), (2, 12)]),
+                commit(2),
+                list(2),
+            ]
+            .map(ok),
+        );
+
+        let report = check_kafka(&history);
+        assert!(report.is_valid(), "{report}");
+    }
+
+    #[test]
+    fn gap_in_a_poll_is_skipped() {
+        let history = History::sequential(
+            [
+                send(10, 0),
+                send(11, 1),
+                send(12, 2),
+                poll(0, &[(0, 10), (2, 12)]),
+            ]
+            .map(ok),
+        );
+
+        let report = check_kafka(&history);
+        assert_eq!(report.skipped, BTreeSet::from([("k".to_string(), 1)]));
+        assert_eq!(report.lost, BTreeSet::from([("k".to_string(), 1)]));
+        assert!(report.nonmonotonic.is_empty());
+    }
+
+    #[test]
+    fn send_acknowledged_during_a_poll_is_lost_but_not_skipped() {
+        let mut history = History::default();
+        history.push("a", EventKind::Invoke, send(10, 0));
+        history.push("a", EventKind::Ok, send(10, 0));
+        history.push("b", EventKind::Invoke, send(11, 1));
+        history.push("c", EventKind::Invoke, send(12, 2));
+        history.push("c", EventKind::Ok, send(12, 2));
+        history.push("d", EventKind::Invoke, poll(0, &[]));
+        history.push("b", EventKind::Ok, send(11, 1));
+        history.push("d", EventKind::Ok, poll(0, &[(0, 10), (2, 12)]));
+
+        let report = check_kafka(&history);
+        assert!(report.skipped.is_empty());
+        assert_eq!(report.lost, BTreeSet::from([("k".to_string(), 1)]));
+    }
+
+    #[test]
+    fn offset_acknowledged_out_of_order_is_nonmonotonic() {
+        let history = History::sequential([send(10, 5), send(11, 3)].map(ok));
+
+        let report = check_kafka(&history);
+        assert_eq!(report.nonmonotonic, [("k".to_string(), 3, 5)]);
+
+        // concurrent sends may be acknowledged in any order
+        let mut history = History::default();
+        history.push("a", EventKind::Invoke, send(10, 5));
+        history.push("b", EventKind::Invoke, send(11, 3));
+        history.push("a", EventKind::Ok, send(10, 5));
+        history.push("b", EventKind::Ok, send(11, 3));
+        assert!(check_kafka(&history).is_valid());
+    }
+
+    #[test]
+    fn inconsistent_and_unordered_polls() {
+        let history =
+            History::sequential([send(10, 0), send(11, 1), poll(0, &[(1, 11), (0, 99)])].map(ok));
+
+        let report = check_kafka(&history);
+        assert_eq!(report.inconsistent, BTreeSet::from([("k".to_string(), 0)]));
+        assert_eq!(report.unordered_polls, [("k".to_string(), vec![1, 0])]);
+    }
+
+    #[test]
+    fn poll_resuming_past_the_commit_is_skipped() {
+        let history = History::sequential(
+            [
+                send(10, 0),
+                send(11, 1),
+                send(12, 2),
+                send(13, 3),
+                poll(0, &[(0, 10), (1, 11)]),
+                commit(0),
+                // 1 was read before, but 2 only is later on
+                poll(3, &[(3, 13)]),
+                poll(2, &[(2, 12), (3, 13)]),
+            ]
+            .map(ok),
+        );
+
+        let report = check_kafka(&history);
+        assert_eq!(report.skipped, BTreeSet::from([("k".to_string(), 2)]));
+        assert!(report.lost.is_empty());
+
+        // resuming from the commit itself skips nothing
+        let history = History::sequential(
+            [
+                send(10, 0),
+                send(11, 1),
+                poll(0, &[(0, 10)]),
+                commit(0),
+                poll(1, &[(1, 11)]),
+            ]
+            .map(ok),
+        );
+        let report = check_kafka(&history);
+        assert!(report.is_valid(), "{report}");
+    }
+
+    #[test]
+    fn committed_offsets_do_not_go_back() {
+        let history = History::sequential([commit(5), list(3)].map(ok));
+
+        let report = check_kafka(&history);
+        assert_eq!(report.committed_regressions, [("k".to_string(), 3, 5)]);
+    }
+
+    #[test]
+    fn empty_history_is_not_valid() {
+        let report = check_kafka(&History::default());
+        assert!(!report.is_valid());
+        assert!(report.to_string().contains("no operations"));
+    }
+}
@@ -0,0 +1,489 @@
+use crate::{
+    history::{ClientOp, EventKind, History, Operation},
+    maelstrom_protocol,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/// A read, write or compare-and-set on one key of a register store, such as
+/// Maelstrom's `lin-kv` workload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegisterOp {
+    /// `value` is `None` until the read completes, or when the key was absent.
+    Read {
+        key: Value,
+        value: Option<Value>,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+    },
+}
+
+impl RegisterOp {
+    pub fn key(&self) -> &Value {
+        match self {
+            Self::Read { key, .. } | Self::Write { key, .. } | Self::Cas { key, .. } => key,
+        }
+    }
+}
+
+/// Register workload bodies. Keys can be any JSON value, unlike in the KV
+/// services.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum RegisterPayload {
+    Read {
+        key: Value,
+    },
+    ReadOk {
+        value: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+    },
+    CasOk,
+    #[serde(other)]
+    Unknown,
+}
+
+impl ClientOp for RegisterOp {
+    type Payload = RegisterPayload;
+
+    fn invoked(_node: &str, request: &RegisterPayload) -> Option<Self> {
+        match request {
+            RegisterPayload::Read { key } => Some(Self::Read {
+                key: key.clone(),
+                value: None,
+            }),
+            RegisterPayload::Write { key, value } => Some(Self::Write {
+                key: key.clone(),
+                value: value.clone(),
+            }),
+            RegisterPayload::Cas { key, from, to } => Some(Self::Cas {
+                key: key.clone(),
+                from: from.clone(),
+                to: to.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn completed(&self, reply: &RegisterPayload) -> Self {
+        match (self, reply) {
+            (Self::Read { key, .. }, RegisterPayload::ReadOk { value }) => Self::Read {
+                key: key.clone(),
+                value: Some(value.clone()),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    fn errored(&self, error: &maelstrom_protocol::Error) -> (EventKind, Self) {
+        match (self, error.code) {
+            // a read of a missing key saw the register empty
+            (Self::Read { .. }, maelstrom_protocol::ErrorCode::KeyDoesNotExist) => {
+                (EventKind::Ok, self.clone())
+            }
+            (_, code) if code.is_definite() => (EventKind::Fail, self.clone()),
+            _ => (EventKind::Info, self.clone()),
+        }
+    }
+}
+
+/// A key whose operations cannot be put in any order consistent with both
+/// real time and a single register.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub key: Value,
+    /// The longest sequence of operations that could be linearized.
+    pub linearized: Vec<Operation<RegisterOp>>,
+    /// The completed operations left over after that sequence.
+    pub remaining: Vec<Operation<RegisterOp>>,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "key {} is not linearizable", self.key)?;
+        writeln!(f, "linearized:")?;
+        for operation in &self.linearized {
+            writeln!(f, "  {} {:?}", operation.process, operation.op)?;
+        }
+        writeln!(f, "could not linearize:")?;
+        for operation in &self.remaining {
+            writeln!(f, "  {} {:?}", operation.process, operation.op)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// Checks a register history for linearizability, key by key, with the
+/// Wing–Gong search and Lowe's memoization of visited states.
+///
+/// Failed operations are left out. Operations with an unknown outcome may
+/// take effect at any point after they were invoked, or not at all.
+pub fn check_linearizable(history: &History<RegisterOp>) -> Result<(), Violation> {
+    let mut keys: Vec<(String, Vec<Operation<RegisterOp>>)> = Vec::new();
+    let mut index = HashMap::new();
+
+    for operation in history.operations() {
+        let relevant = match (&operation.kind, &operation.op) {
+            (EventKind::Ok, _) => true,
+            (EventKind::Fail, _) => false,
+            // reads without a result have no effect
+            (_, RegisterOp::Read { .. }) => false,
+            _ => true,
+        };
+        if !relevant {
+            continue;
+        }
+
+        let key = operation.op.key().to_string();
+        let i = *index.entry(key.clone()).or_insert_with(|| {
+            keys.push((key, Vec::new()));
+            keys.len() - 1
+        });
+        keys[i].1.push(operation);
+    }
+
+    for (_, operations) in keys {
+        search(&operations)?;
+    }
+
+    Ok(())
+}
+
+/// The register, as an index into the distinct values of one key.
+type Register = Option<usize>;
+
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Read(Register),
+    Write(usize),
+    Cas(usize, usize),
+}
+
+impl Step {
+    fn apply(self, register: Register) -> Option<Register> {
+        match self {
+            Self::Read(value) => (value == register).then_some(register),
+            Self::Write(value) => Some(Some(value)),
+            Self::Cas(from, to) => (register == Some(from)).then_some(Some(to)),
+        }
+    }
+}
+
+const NIL: usize = usize::MAX;
+const HEAD: usize = 0;
+
+/// One call or return, threaded on a doubly linked list in history order.
+struct Entry {
+    op: usize,
+    is_call: bool,
+    /// For a call, the entry of its return, if the operation completed.
+    ret: Option<usize>,
+}
+
+fn search(operations: &[Operation<RegisterOp>]) -> Result<(), Violation> {
+    let mut values = Vec::new();
+    let steps: Vec<Step> = operations
+        .iter()
+        .map(|operation| match &operation.op {
+            RegisterOp::Read { value, .. } => {
+                Step::Read(value.as_ref().map(|v| intern(&mut values, v)))
+            }
+            RegisterOp::Write { value, .. } => Step::Write(intern(&mut values, value)),
+            RegisterOp::Cas { from, to, .. } => {
+                Step::Cas(intern(&mut values, from), intern(&mut values, to))
+            }
+        })
+        .collect();
+
+    // calls and returns in history order, after a sentinel head
+    let mut events: Vec<(usize, usize, bool)> = Vec::new();
+    for (i, operation) in operations.iter().enumerate() {
+        events.push((operation.call, i, true));
+        if let (EventKind::Ok, Some(ret)) = (operation.kind, operation.ret) {
+            events.push((ret, i, false));
+        }
+    }
+    events.sort_by_key(|(at, _, _)| *at);
+
+    let mut entries = vec![Entry {
+        op: NIL,
+        is_call: false,
+        ret: None,
+    }];
+    let mut call_entry = vec![NIL; operations.len()];
+    for (_, op, is_call) in &events {
+        if *is_call {
+            call_entry[*op] = entries.len();
+        } else {
+            entries[call_entry[*op]].ret = Some(entries.len());
+        }
+        entries.push(Entry {
+            op: *op,
+            is_call: *is_call,
+            ret: None,
+        });
+    }
+
+    let n = entries.len();
+    let mut next: Vec<usize> = (1..=n).map(|i| if i == n { NIL } else { i }).collect();
+    let mut prev: Vec<usize> = (0..n).map(|i| i.wrapping_sub(1)).collect();
+
+    let unlink = |next: &mut Vec<usize>, prev: &mut Vec<usize>, e: usize| {
+        next[prev[e]] = next[e];
+        if next[e] != NIL {
+            prev[next[e]] = prev[e];
+        }
+    };
+    let relink = |next: &mut Vec<usize>, prev: &mut Vec<usize>, e: usize| {
+        next[prev[e]] = e;
+        if next[e] != NIL {
+            prev[next[e]] = e;
+        }
+    };
+
+    let mut remaining = entries.iter().filter(|e| !e.is_call && e.op != NIL).count();
+    let mut register: Register = None;
+    let mut linearized = vec![0u64; operations.len().div_ceil(64)];
+    let mut seen: HashSet<(Vec<u64>, Register)> = HashSet::new();
+    let mut stack: Vec<(usize, Register)> = Vec::new();
+    let mut longest: Vec<usize> = Vec::new();
+    let mut entry = next[HEAD];
+
+    while remaining > 0 {
+        if entry != NIL && entries[entry].is_call {
+            let op = entries[entry].op;
+            if let Some(after) = steps[op].apply(register) {
+                let mut bits = linearized.clone();
+                bits[op / 64] |= 1 << (op % 64);
+
+                if seen.insert((bits.clone(), after)) {
+                    stack.push((entry, register));
+                    register = after;
+                    linearized = bits;
+
+                    unlink(&mut next, &mut prev, entry);
+                    if let Some(ret) = entries[entry].ret {
+                        unlink(&mut next, &mut prev, ret);
+                        remaining -= 1;
+                    }
+
+                    entry = next[HEAD];
+                    continue;
+                }
+            }
+
+            entry = next[entry];
+            continue;
+        }
+
+        // an operation returned before it could be linearized: backtrack
+        if stack.len() > longest.len() {
+            longest = stack.iter().map(|(e, _)| entries[*e].op).collect();
+        }
+        let Some((call, before)) = stack.pop() else {
+            return Err(violation(operations, &longest));
+        };
+
+        let op = entries[call].op;
+        register = before;
+        linearized[op / 64] &= !(1 << (op % 64));
+
+        if let Some(ret) = entries[call].ret {
+            relink(&mut next, &mut prev, ret);
+            remaining += 1;
+        }
+        relink(&mut next, &mut prev, call);
+
+        entry = next[call];
+    }
+
+    Ok(())
+}
+
+fn intern<'a>(values: &mut Vec<&'a Value>, value: &'a Value) -> usize {
+    match values.iter().position(|v| *v == value) {
+        Some(i) => i,
+        None => {
+            values.push(value);
+            values.len() - 1
+        }
+    }
+}
+
+fn violation(operations: &[Operation<RegisterOp>], longest: &[usize]) -> Violation {
+    let done: HashSet<_> = longest.iter().copied().collect();
+
+    Violation {
+        key: operations[0].op.key().clone(),
+        linearized: longest.iter().map(|i| operations[*i].clone()).collect(),
+        remaining: operations
+            .iter()
+            .enumerate()
+            .filter(|(i, operation)| !done.contains(i) && operation.kind == EventKind::Ok)
+            .map(|(_, operation)| operation.clone())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn write(value: u64) -> RegisterOp {
+        RegisterOp::Write {
+            key: json!("x"),
+            value: json!(value),
+        }
+    }
+
+    fn read(value: Option<u64>) -> RegisterOp {
+        RegisterOp::Read {
+            key: json!("x"),
+            value: value.map(|v| json!(v)),
+        }
+    }
+
+    fn cas(from: u64, to: u64) -> RegisterOp {
+        RegisterOp::Cas {
+            key: json!("x"),
+            from: json!(from),
+            to: json!(to),
+        }
+    }
+
+    /// Runs `ops` one after another, each by its own process.
+    #[test]
+    fn concurrent_operations_are_linearizable() {
+        let mut history = History::default();
+        history.push("a", EventKind::Invoke, write(1));
+        history.push("b", EventKind::Invoke, read(None));
+        history.push("c", EventKind::Invoke, read(None));
+        // b read the write before it returned, c read from before it
+        history.push("b", EventKind::Ok, read(Some(1)));
+        history.push("c", EventKind::Ok, read(None));
+        history.push("a", EventKind::Ok, write(1));
+        history.push("d", EventKind::Invoke, cas(1, 2));
+        history.push("d", EventKind::Ok, cas(1, 2));
+        history.push("e", EventKind::Invoke, read(None));
+        history.push("e", EventKind::Ok, read(Some(2)));
+
+        check_linearizable(&history).unwrap();
+    }
+
+    #[test]
+    fn stale_read_is_a_violation() {
+        let history = History::sequential([
+            (EventKind::Ok, write(1)),
+            (EventKind::Ok, write(2)),
+            (EventKind::Ok, read(Some(1))),
+        ]);
+
+        let violation = check_linearizable(&history).unwrap_err();
+        assert_eq!(violation.key, json!("x"));
+        assert_eq!(violation.linearized.len(), 2);
+        assert_eq!(violation.remaining.len(), 1);
+        assert_eq!(violation.remaining[0].op, read(Some(1)));
+    }
+
+    #[test]
+    fn failed_cas_has_no_effect() {
+        let history = History::sequential([
+            (EventKind::Ok, write(1)),
+            (EventKind::Fail, cas(2, 3)),
+            (EventKind::Ok, read(Some(1))),
+        ]);
+        check_linearizable(&history).unwrap();
+
+        // the same cas claiming to succeed could not have
+        let history = History::sequential([
+            (EventKind::Ok, write(1)),
+            (EventKind::Ok, cas(2, 3)),
+            (EventKind::Ok, read(Some(3))),
+        ]);
+        check_linearizable(&history).unwrap_err();
+    }
+
+    #[test]
+    fn info_write_may_or_may_not_take_effect() {
+        let taken = History::sequential([
+            (EventKind::Ok, write(1)),
+            (EventKind::Info, write(2)),
+            (EventKind::Ok, read(Some(2))),
+        ]);
+        check_linearizable(&taken).unwrap();
+
+        let not_taken = History::sequential([
+            (EventKind::Ok, write(1)),
+            (EventKind::Info, write(2)),
+            (EventKind::Ok, read(Some(1))),
+        ]);
+        check_linearizable(&not_taken).unwrap();
+
+        // once it is seen, it cannot be undone
+        let undone = History::sequential([
+            (EventKind::Ok, write(1)),
+            (EventKind::Info, write(2)),
+            (EventKind::Ok, read(Some(2))),
+            (EventKind::Ok, read(Some(1))),
+        ]);
+        check_linearizable(&undone).unwrap_err();
+    }
+
+    #[test]
+    fn info_write_takes_effect_after_it_was_invoked() {
+        let history =
+            History::sequential([(EventKind::Ok, read(Some(2))), (EventKind::Info, write(2))]);
+        check_linearizable(&history).unwrap_err();
+    }
+
+    #[test]
+    fn keys_are_checked_separately() {
+        let mut history = History::sequential([(EventKind::Ok, write(1))]);
+        let other = RegisterOp::Read {
+            key: json!("y"),
+            value: None,
+        };
+        history.push("q", EventKind::Invoke, other.clone());
+        history.push("q", EventKind::Ok, other);
+
+        check_linearizable(&history).unwrap();
+    }
+
+    #[test]
+    fn history_from_messages() {
+        let lines = [
+            r#"{"src":"c1","dest":"n1","body":{"type":"write","msg_id":1,"key":"x","value":1}}"#,
+            r#"{"src":"n1","dest":"c1","body":{"type":"write_ok","in_reply_to":1}}"#,
+            r#"{"src":"c2","dest":"n1","body":{"type":"cas","msg_id":1,"key":"x","from":5,"to":6}}"#,
+            r#"{"src":"n1","dest":"c2","body":{"type":"error","in_reply_to":1,"code":22}}"#,
+            r#"{"src":"c2","dest":"n1","body":{"type":"read","msg_id":2,"key":"x"}}"#,
+            r#"{"src":"n1","dest":"c2","body":{"type":"read_ok","in_reply_to":2,"value":1}}"#,
+        ];
+        let history = History::<RegisterOp>::from_lines(lines);
+        check_linearizable(&history).unwrap();
+
+        let kinds: Vec<_> = history.operations().iter().map(|o| o.kind).collect();
+        assert_eq!(kinds, [EventKind::Ok, EventKind::Fail, EventKind::Ok]);
+    }
+}
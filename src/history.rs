@@ -0,0 +1,368 @@
+//! Client-observed histories: what each client asked for and what it was told.
+//!
+//! Events follow Jepsen's model. Every operation starts with an `invoke` and
+//! ends with `ok` (it happened), `fail` (it definitely did not) or `info` (it
+//! may or may not have). Operations that never got an answer stay open.
+
+use crate::{
+    actors::is_client,
+    maelstrom_protocol,
+    trace::{Direction, TraceEntry},
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+#[derive(Debug, Clone)]
+pub struct Event<O> {
+    pub process: String,
+    pub kind: EventKind,
+    pub op: O,
+}
+
+/// An operation a client can perform, built from request and reply payloads.
+pub trait ClientOp: Clone {
+    type Payload: DeserializeOwned;
+
+    /// The operation a request to `node` asks for, if it is one this history
+    /// tracks.
+    fn invoked(node: &str, request: &Self::Payload) -> Option<Self>;
+
+    /// The operation as a successful reply describes it.
+    fn completed(&self, reply: &Self::Payload) -> Self;
+
+    /// How an error reply completes the operation. By default, definite errors
+    /// fail it and the rest leave its outcome unknown.
+    fn errored(&self, error: &maelstrom_protocol::Error) -> (EventKind, Self) {
+        match error.code.is_definite() {
+            true => (EventKind::Fail, self.clone()),
+            false => (EventKind::Info, self.clone()),
+        }
+    }
+}
+
+/// An invocation paired with its completion.
+#[derive(Debug, Clone)]
+pub struct Operation<O> {
+    pub process: String,
+    /// Index of the `invoke` event.
+    pub call: usize,
+    /// Index of the completing event, if there was one.
+    pub ret: Option<usize>,
+    /// `Invoke` while the operation is still open.
+    pub kind: EventKind,
+    pub op: O,
+}
+
+#[derive(Debug, Clone)]
+pub struct History<O> {
+    events: Vec<Event<O>>,
+}
+
+impl<O> Default for History<O> {
+    fn default() -> Self {
+        Self { events: Vec::new() }
+    }
+}
+
+impl<O: Clone> History<O> {
+    pub fn events(&self) -> &[Event<O>] {
+        &self.events
+    }
+
+    /// Appends an event and returns its index.
+    pub fn push(&mut self, process: impl Into<String>, kind: EventKind, op: O) -> usize {
+        self.events.push(Event {
+            process: process.into(),
+            kind,
+            op,
+        });
+        self.events.len() - 1
+    }
+
+    /// Pairs every invocation with the next completion by the same process.
+    pub fn operations(&self) -> Vec<Operation<O>> {
+        let mut operations = Vec::new();
+        let mut open: HashMap<&str, usize> = HashMap::new();
+
+        for (i, event) in self.events.iter().enumerate() {
+            match event.kind {
+                EventKind::Invoke => {
+                    open.insert(&event.process, operations.len());
+                    operations.push(Operation {
+                        process: event.process.clone(),
+                        call: i,
+                        ret: None,
+                        kind: EventKind::Invoke,
+                        op: event.op.clone(),
+                    });
+                }
+                kind => {
+                    if let Some(j) = open.remove(event.process.as_str()) {
+                        let operation: &mut Operation<O> = &mut operations[j];
+                        operation.ret = Some(i);
+                        operation.kind = kind;
+                        operation.op = event.op.clone();
+                    }
+                }
+            }
+        }
+
+        operations
+    }
+
+    /// One process per operation, each completed before the next is invoked.
+    #[cfg(test)]
+    pub(crate) fn sequential(ops: impl IntoIterator<Item = (EventKind, O)>) -> Self {
+        let mut history = Self::default();
+        for (i, (kind, op)) in ops.into_iter().enumerate() {
+            let process = format!("p{i}");
+            history.push(&process, EventKind::Invoke, op.clone());
+            history.push(&process, kind, op);
+        }
+        history
+    }
+}
+
+impl<O: ClientOp> History<O> {
+    /// Builds a history from the messages between clients and the cluster, in
+    /// the order they were seen. Each client request is its own process, so
+    /// clients may have several requests in flight.
+    pub fn from_messages(
+        messages: impl IntoIterator<Item = maelstrom_protocol::Message<Value>>,
+    ) -> Self {
+        let mut history = Self::default();
+        let mut pending: HashMap<(String, usize), O> = HashMap::new();
+
+        for message in messages {
+            if is_client(&message.src) && !is_client(&message.dst) {
+                let Some(id) = message.body.id else {
+                    continue;
+                };
+                let Ok(request) = serde_json::from_value::<O::Payload>(message.body.payload) else {
+                    continue;
+                };
+                let Some(op) = O::invoked(&message.dst, &request) else {
+                    continue;
+                };
+
+                history.push(process(&message.src, id), EventKind::Invoke, op.clone());
+                pending.insert((message.src, id), op);
+            } else if is_client(&message.dst) {
+                let Some(id) = message.body.in_reply_to else {
+                    continue;
+                };
+                let Some(op) = pending.remove(&(message.dst.clone(), id)) else {
+                    continue;
+                };
+
+                let reply = serde_json::from_value::<maelstrom_protocol::Reply<O::Payload>>(
+                    message.body.payload,
+                );
+                let (kind, op) = match reply {
+                    Ok(maelstrom_protocol::Reply::Ok(payload)) => {
+                        (EventKind::Ok, op.completed(&payload))
+                    }
+                    Ok(maelstrom_protocol::Reply::Err(error)) => op.errored(&error),
+                    Err(_) => (EventKind::Info, op),
+                };
+                history.push(process(&message.dst, id), kind, op);
+            }
+        }
+
+        history
+    }
+
+    /// Like [`History::from_messages`], for JSONL lines of bare messages such
+    /// as a node's stdin and stdout. Lines that are not messages are skipped,
+    /// so trace files go through [`History::from_trace`] instead.
+    pub fn from_lines<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        Self::from_messages(
+            lines
+                .into_iter()
+                .filter_map(|line| serde_json::from_str(line).ok()),
+        )
+    }
+
+    /// Like [`History::from_messages`], for node traces. The entries of
+    /// several nodes can be passed together and are merged by time. Each
+    /// client message counts once, even if it was recorded more than once.
+    pub fn from_trace(entries: &[TraceEntry]) -> Self {
+        let mut entries: Vec<&TraceEntry> = entries.iter().collect();
+        entries.sort_by_key(|entry| entry.time);
+
+        let mut seen = HashSet::new();
+        Self::from_messages(
+            entries
+                .into_iter()
+                .filter(|entry| {
+                    let party = match entry.direction {
+                        Direction::In => "src",
+                        Direction::Out => "dest",
+                    };
+                    entry.message[party].as_str().is_some_and(is_client)
+                })
+                .filter(|entry| seen.insert(entry.message.to_string()))
+                .filter_map(|entry| serde_json::from_value(entry.message.clone()).ok()),
+        )
+    }
+}
+
+fn process(client: &str, msg_id: usize) -> String {
+    format!("{client}/{msg_id}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::RegisterOp;
+    use serde_json::json;
+
+    fn write(value: u64) -> RegisterOp {
+        RegisterOp::Write {
+            key: json!("x"),
+            value: json!(value),
+        }
+    }
+
+    /// Each operation's process, call, return and outcome.
+    fn pairs(history: &History<RegisterOp>) -> Vec<(&str, usize, Option<usize>, EventKind)> {
+        history
+            .operations()
+            .iter()
+            .map(|o| {
+                let process = history.events()[o.call].process.as_str();
+                assert_eq!(o.process, process);
+                (process, o.call, o.ret, o.kind)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn operations_pair_invocations_per_process() {
+        let mut history = History::default();
+        history.push("a", EventKind::Invoke, write(1));
+        history.push("b", EventKind::Invoke, write(2));
+        history.push("b", EventKind::Fail, write(2));
+        history.push("a", EventKind::Ok, write(1));
+        history.push("a", EventKind::Invoke, write(3));
+        history.push("c", EventKind::Info, write(4));
+
+        assert_eq!(
+            pairs(&history),
+            [
+                ("a", 0, Some(3), EventKind::Ok),
+                ("b", 1, Some(2), EventKind::Fail),
+                // still open, and c's completion had no invocation
+                ("a", 4, None, EventKind::Invoke),
+            ]
+        );
+    }
+
+    #[test]
+    fn completion_replaces_the_op() {
+        let mut history = History::default();
+        let read = |value: Option<u64>| RegisterOp::Read {
+            key: json!("x"),
+            value: value.map(|v| json!(v)),
+        };
+        history.push("a", EventKind::Invoke, read(None));
+        history.push("a", EventKind::Ok, read(Some(1)));
+
+        assert_eq!(history.operations()[0].op, read(Some(1)));
+    }
+
+    #[test]
+    fn from_messages_pairs_by_client_and_msg_id() {
+        let lines = [
+            // two requests from c1 in flight at once, answered out of order
+            r#"{"src":"c1","dest":"n1","body":{"type":"write","msg_id":1,"key":"x","value":1}}"#,
+            r#"{"src":"c1","dest":"n2","body":{"type":"write","msg_id":2,"key":"x","value":2}}"#,
+            r#"{"src":"n2","dest":"c1","body":{"type":"error","in_reply_to":2,"code":0}}"#,
+            r#"{"src":"n1","dest":"c1","body":{"type":"write_ok","in_reply_to":1}}"#,
+            // node-to-node traffic and unmatched replies are ignored
+            r#"{"src":"n1","dest":"n2","body":{"type":"write","msg_id":3,"key":"x","value":3}}"#,
+            r#"{"src":"n1","dest":"c1","body":{"type":"write_ok","in_reply_to":9}}"#,
+            r#"{"src":"c2","dest":"n1","body":{"type":"write","msg_id":1,"key":"x","value":4}}"#,
+            r#"{"src":"n1","dest":"c2","body":{"type":"error","in_reply_to":1,"code":14}}"#,
+            r#"{"src":"c3","dest":"n1","body":{"type":"write","msg_id":1,"key":"x","value":5}}"#,
+            "not a message",
+        ];
+        let history = History::<RegisterOp>::from_lines(lines);
+
+        assert_eq!(history.events().len(), 7);
+        assert_eq!(
+            pairs(&history),
+            [
+                ("c1/1", 0, Some(3), EventKind::Ok),
+                ("c1/2", 1, Some(2), EventKind::Info),
+                ("c2/1", 4, Some(5), EventKind::Fail),
+                ("c3/1", 6, None, EventKind::Invoke),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn from_trace_merges_recorded_node_traces() {
+        use crate::trace::{self, Tracer};
+
+        let dir = std::env::temp_dir().join(format!("glomers-history-{}", std::process::id()));
+        let (n1, n1_written) = Tracer::spawn(dir.clone());
+        let (n2, n2_written) = Tracer::spawn(dir.clone());
+        let init = |node: &str| {
+            format!(
+                r#"{{"src":"c0","dest":"{node}","body":{{"type":"init","msg_id":1,"node_id":"{node}","node_ids":["n1","n2"]}}}}"#
+            )
+        };
+        let steps = [
+            (&n1, Direction::In, init("n1")),
+            (&n2, Direction::In, init("n2")),
+            (&n1, Direction::In, r#"{"src":"c1","dest":"n1","body":{"type":"write","msg_id":1,"key":"x","value":1}}"#.to_string()),
+            (&n1, Direction::Out, r#"{"src":"n1","dest":"n2","body":{"type":"write","msg_id":1,"key":"x","value":1}}"#.to_string()),
+            (&n2, Direction::In, r#"{"src":"n1","dest":"n2","body":{"type":"write","msg_id":1,"key":"x","value":1}}"#.to_string()),
+            (&n1, Direction::Out, r#"{"src":"n1","dest":"c1","body":{"type":"write_ok","in_reply_to":1}}"#.to_string()),
+            // a duplicated request
+            (&n2, Direction::In, r#"{"src":"c2","dest":"n2","body":{"type":"read","msg_id":1,"key":"x"}}"#.to_string()),
+            (&n2, Direction::In, r#"{"src":"c2","dest":"n2","body":{"type":"read","msg_id":1,"key":"x"}}"#.to_string()),
+            (&n2, Direction::Out, r#"{"src":"n2","dest":"c2","body":{"type":"read_ok","in_reply_to":1,"value":1}}"#.to_string()),
+        ];
+        for (tracer, direction, line) in steps {
+            tracer.record(direction, line.as_bytes());
+            // distinct timestamps, so the merge has an order to restore
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        drop((n1, n2));
+        n1_written.await.unwrap();
+        n2_written.await.unwrap();
+
+        // one node's file after the other, as a directory listing gives them
+        let mut entries = trace::read(dir.join("n2.jsonl")).unwrap();
+        entries.extend(trace::read(dir.join("n1.jsonl")).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let history = History::<RegisterOp>::from_trace(&entries);
+        assert_eq!(
+            pairs(&history),
+            [
+                ("c1/1", 0, Some(1), EventKind::Ok),
+                ("c2/1", 2, Some(3), EventKind::Ok),
+            ]
+        );
+        assert_eq!(
+            history.events()[3].op,
+            RegisterOp::Read {
+                key: json!("x"),
+                value: Some(json!(1)),
+            }
+        );
+        crate::check::check_linearizable(&history).unwrap();
+    }
+}
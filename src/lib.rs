@@ -1,2 +1,9 @@
 pub mod actors;
+pub mod check;
+pub mod history;
 pub mod maelstrom_protocol;
+pub mod rng;
+pub mod sim;
+pub mod testkit;
+pub mod trace;
+pub mod workloads;
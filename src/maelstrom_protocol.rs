@@ -1,6 +1,5 @@
-use std::collections::HashSet;
-
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::RawValue;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message<P> {
@@ -20,14 +19,220 @@ pub struct Body<P> {
     pub payload: P,
 }
 
+/// A message whose body has not been parsed yet.
+#[derive(Debug, Deserialize)]
+pub struct Envelope {
+    pub src: String,
+    #[serde(rename = "dest")]
+    pub dst: String,
+    pub body: Box<RawValue>,
+}
+
+/// The fields every message body carries, regardless of its payload.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Header {
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    #[serde(rename = "msg_id")]
+    pub id: Option<usize>,
+    pub in_reply_to: Option<usize>,
+}
+
+impl Header {
+    /// Whether the message expects an answer.
+    pub fn is_request(&self) -> bool {
+        let is_reply = match self.kind.as_deref() {
+            Some(kind) => kind == "error" || kind.ends_with("_ok"),
+            None => false,
+        };
+
+        self.id.is_some() && self.in_reply_to.is_none() && !is_reply
+    }
+}
+
+impl Envelope {
+    pub fn header(&self) -> serde_json::Result<Header> {
+        serde_json::from_str(self.body.get())
+    }
+
+    /// Parses the body into the given payload.
+    pub fn parse<P: DeserializeOwned>(self) -> serde_json::Result<Message<P>> {
+        Ok(Message {
+            src: self.src,
+            dst: self.dst,
+            body: serde_json::from_str(self.body.get())?,
+        })
+    }
+}
+
+/// A message body, minus `msg_id` and `in_reply_to`. Node payloads need no
+/// catch-all variant: the runtime answers types they lack with not-supported.
 pub trait Payload: std::fmt::Debug + Sized + Send + Clone + Serialize + DeserializeOwned {}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InitPayload {
     pub node_id: String,
-    pub node_ids: HashSet<String>,
+    pub node_ids: Vec<String>,
+}
+
+/// The `init` handshake every node receives before any workload message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum Handshake {
+    Init(InitPayload),
+    InitOk,
+}
+
+impl Payload for Handshake {}
+
+/// Error codes as defined by the Maelstrom protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "u32", into = "u32")]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    Other(u32),
+}
+
+impl ErrorCode {
+    /// Whether the error guarantees that the request did not take place.
+    pub fn is_definite(&self) -> bool {
+        !matches!(self, Self::Timeout | Self::Crash | Self::Other(_))
+    }
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        match code {
+            0 => Self::Timeout,
+            1 => Self::NodeNotFound,
+            10 => Self::NotSupported,
+            11 => Self::TemporarilyUnavailable,
+            12 => Self::MalformedRequest,
+            13 => Self::Crash,
+            14 => Self::Abort,
+            20 => Self::KeyDoesNotExist,
+            21 => Self::KeyAlreadyExists,
+            22 => Self::PreconditionFailed,
+            30 => Self::TxnConflict,
+            v => Self::Other(v),
+        }
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Other(v) => v,
+        }
+    }
+}
+
+/// The body of a Maelstrom `error` message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename = "error", from = "ErrorBody")]
+pub struct Error {
+    pub code: ErrorCode,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+/// Serde does not check the tag of a tagged struct, so errors are read through
+/// this enum, which only accepts `"type": "error"`.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+enum ErrorBody {
+    Error {
+        code: ErrorCode,
+        #[serde(default)]
+        text: Option<String>,
+    },
 }
 
+impl From<ErrorBody> for Error {
+    fn from(ErrorBody::Error { code, text }: ErrorBody) -> Self {
+        Self { code, text }
+    }
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: Some(text.into()),
+        }
+    }
+}
+
+impl From<ErrorCode> for Error {
+    fn from(code: ErrorCode) -> Self {
+        Self { code, text: None }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.text {
+            Some(text) => write!(f, "error {}: {}", u32::from(self.code), text),
+            None => write!(f, "error {}", u32::from(self.code)),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Payload for Error {}
+
+/// A reply body: either the expected payload or a Maelstrom error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Reply<P> {
+    Err(Error),
+    Ok(P),
+}
+
+impl<P> Reply<P> {
+    pub fn into_result(self) -> Result<P, Error> {
+        match self {
+            Self::Ok(v) => Ok(v),
+            Self::Err(e) => Err(e),
+        }
+    }
+}
+
+impl<P> From<Result<P, Error>> for Reply<P> {
+    fn from(result: Result<P, Error>) -> Self {
+        match result {
+            Ok(v) => Self::Ok(v),
+            Err(e) => Self::Err(e),
+        }
+    }
+}
+
+impl<P: Payload> Payload for Reply<P> {}
+
 impl<P> Message<P> {
     pub fn new(src: String, dst: String, payload: P) -> Self {
         Self {
@@ -41,22 +246,91 @@ impl<P> Message<P> {
         }
     }
 
-    pub fn make_response(&self, payload: P) -> Self {
-        Self {
+    pub fn make_response(&self, payload: P) -> Message<Reply<P>> {
+        self.make_reply(Reply::Ok(payload))
+    }
+
+    pub fn make_error_response(&self, error: impl Into<Error>) -> Message<Reply<P>> {
+        self.make_reply(Reply::Err(error.into()))
+    }
+
+    fn make_reply(&self, reply: Reply<P>) -> Message<Reply<P>> {
+        Message {
             src: self.dst.clone(),
             dst: self.src.clone(),
             body: Body {
                 id: None,
                 in_reply_to: self.body.id,
-                payload,
+                payload: reply,
             },
         }
     }
 }
 
-impl<P> xtra::Message for Message<P>
-where
-    P: Payload + 'static,
-{
-    type Result = Option<Message<P>>;
+impl<P> Message<Reply<P>> {
+    /// Splits a reply into its payload message or the error it carries.
+    pub fn into_result(self) -> Result<Message<P>, Error> {
+        let payload = self.body.payload.into_result()?;
+
+        Ok(Message {
+            src: self.src,
+            dst: self.dst,
+            body: Body {
+                id: self.body.id,
+                in_reply_to: self.body.in_reply_to,
+                payload,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[serde(tag = "type")]
+    enum Ping {
+        Pong { code: u32 },
+    }
+
+    #[test]
+    fn reply_with_code_field_is_not_an_error() {
+        let reply: Message<Reply<Ping>> = serde_json::from_str(
+            r#"{"src":"n2","dest":"n1","body":{"type":"pong","code":1,"in_reply_to":3}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            reply.into_result().unwrap().body.payload,
+            Ping::Pong { code: 1 }
+        );
+    }
+
+    #[test]
+    fn error_reply_is_an_error() {
+        let reply: Message<Reply<Ping>> = serde_json::from_str(
+            r#"{"src":"n2","dest":"n1","body":{"type":"error","code":1,"in_reply_to":3}}"#,
+        )
+        .unwrap();
+
+        let error = reply.into_result().unwrap_err();
+        assert_eq!(error.code, ErrorCode::NodeNotFound);
+        assert_eq!(error.text, None);
+    }
+
+    #[test]
+    fn error_round_trips() {
+        let error = Error::new(ErrorCode::PreconditionFailed, "expected 1");
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"type": "error", "code": 22, "text": "expected 1"})
+        );
+
+        let error: Error = serde_json::from_value(json).unwrap();
+        assert_eq!(error.code, ErrorCode::PreconditionFailed);
+        assert!(serde_json::from_str::<Error>(r#"{"type":"pong","code":22}"#).is_err());
+    }
 }
@@ -0,0 +1,504 @@
+//! Runs several nodes in one process over a simulated network.
+//!
+//! Latency, loss and duplication are drawn from a seeded [`Rng`], so the same
+//! seed and client inputs give the same run. Run it on a current-thread tokio
+//! runtime with a paused clock, or give it a [`actors::ManualClock`], to take
+//! the real clock out of the picture too. Nodes ignore the trace directory
+//! and fault settings in the environment, since those would change the run.
+//!
+//! `seq-kv`, `lin-kv` and `lww-kv` are answered by in-memory [`KvStore`]s.
+
+use crate::{actors, maelstrom_protocol};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, WriteHalf},
+    sync::mpsc,
+    task,
+};
+
+mod kv;
+
+pub use crate::rng::Rng;
+pub use kv::*;
+
+/// The client the simulator sends `init` from. Replies to it are dropped.
+pub const INIT_CLIENT: &str = "c0";
+
+/// How many bytes can sit in a node's input or output before writes wait.
+const PIPE_CAPACITY: usize = 1 << 20;
+
+pub struct Simulation {
+    node_ids: Vec<String>,
+    events: mpsc::UnboundedSender<Event>,
+    client_messages: mpsc::UnboundedReceiver<maelstrom_protocol::Envelope>,
+    /// Everything clients sent and received, in the order they did.
+    client_log: Vec<maelstrom_protocol::Message<Value>>,
+    next_id: usize,
+    network: task::JoinHandle<Vec<String>>,
+    nodes: Vec<task::JoinHandle<()>>,
+}
+
+pub struct SimulationBuilder {
+    seed: u64,
+    nodes: usize,
+    faults: Faults,
+    services: HashMap<String, KvStore>,
+    clock: Arc<dyn actors::Clock>,
+}
+
+/// What can go wrong with a message between two nodes.
+#[derive(Debug, Clone)]
+struct Faults {
+    min_latency: Duration,
+    max_latency: Duration,
+    loss: f64,
+    duplication: f64,
+}
+
+enum Event {
+    Line(String),
+    Partition(HashMap<String, usize>),
+    Shutdown,
+}
+
+impl Simulation {
+    pub fn builder(seed: u64) -> SimulationBuilder {
+        SimulationBuilder {
+            seed,
+            nodes: 1,
+            faults: Faults {
+                min_latency: Duration::ZERO,
+                max_latency: Duration::ZERO,
+                loss: 0.0,
+                duplication: 0.0,
+            },
+            services: KvStore::maelstrom(seed),
+            clock: Arc::new(actors::TokioClock),
+        }
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// Sends a message from a client, as is.
+    pub fn send<P: Serialize>(&mut self, message: &maelstrom_protocol::Message<P>) {
+        let line = serde_json::to_string(message).expect("could not serialize message");
+        self.client_log
+            .push(serde_json::from_str(&line).expect("message is valid JSON"));
+        let _ = self.events.send(Event::Line(line));
+    }
+
+    /// Sends a request from a client and returns the `msg_id` it was given.
+    pub fn request<P: Serialize>(&mut self, src: &str, dest: &str, payload: P) -> usize {
+        self.next_id += 1;
+
+        let mut message = maelstrom_protocol::Message::new(src.into(), dest.into(), payload);
+        message.body.id = Some(self.next_id);
+        self.send(&message);
+
+        self.next_id
+    }
+
+    /// The next message addressed to anything but a node.
+    pub async fn recv(&mut self) -> Option<maelstrom_protocol::Envelope> {
+        let envelope = self.client_messages.recv().await?;
+        if let Ok(body) = serde_json::from_str(envelope.body.get()) {
+            self.client_log.push(maelstrom_protocol::Message {
+                src: envelope.src.clone(),
+                dst: envelope.dst.clone(),
+                body,
+            });
+        }
+        Some(envelope)
+    }
+
+    /// Everything clients sent and received so far, for building a
+    /// [`crate::history::History`].
+    pub fn client_log(&self) -> &[maelstrom_protocol::Message<Value>] {
+        &self.client_log
+    }
+
+    /// Splits the nodes into groups that cannot reach each other. Nodes not
+    /// in any group are cut off from everyone.
+    pub fn partition(&self, groups: &[&[&str]]) {
+        let mut components = HashMap::new();
+        for (i, group) in groups.iter().enumerate() {
+            components.extend(group.iter().map(|n| (n.to_string(), i)));
+        }
+        for (i, n) in self.node_ids.iter().enumerate() {
+            components.entry(n.clone()).or_insert(groups.len() + i);
+        }
+
+        let _ = self.events.send(Event::Partition(components));
+    }
+
+    /// Lets every node reach every other node again.
+    pub fn heal(&self) {
+        let _ = self.events.send(Event::Partition(HashMap::new()));
+    }
+
+    /// Closes every node's input and waits for the nodes to stop. Messages
+    /// still in flight are lost. Returns every message the network delivered,
+    /// in the order it did.
+    pub async fn shutdown(self) -> Vec<String> {
+        let _ = self.events.send(Event::Shutdown);
+        let delivered = self.network.await.unwrap_or_default();
+        for node in self.nodes {
+            let _ = node.await;
+        }
+        delivered
+    }
+}
+
+impl SimulationBuilder {
+    /// Runs `n` nodes, named `n1` to `n{n}`.
+    pub fn nodes(mut self, n: usize) -> Self {
+        self.nodes = n;
+        self
+    }
+
+    /// Delays each message by a duration in `min..=max`.
+    pub fn latency(mut self, min: Duration, max: Duration) -> Self {
+        self.faults.min_latency = min;
+        self.faults.max_latency = max.max(min);
+        self
+    }
+
+    /// Drops messages between nodes with probability `p`.
+    pub fn loss(mut self, p: f64) -> Self {
+        self.faults.loss = p;
+        self
+    }
+
+    /// Delivers messages between nodes twice with probability `p`.
+    pub fn duplication(mut self, p: f64) -> Self {
+        self.faults.duplication = p;
+        self
+    }
+
+    /// Answers requests to `name` with `store`, replacing any store already
+    /// registered under that name.
+    pub fn service(mut self, name: &str, store: KvStore) -> Self {
+        self.services.insert(name.to_string(), store);
+        self
+    }
+
+    /// Sets the clock message latency is measured against. Every node's
+    /// runtime gets it too.
+    pub fn clock(mut self, clock: Arc<dyn actors::Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Starts every node with `make_node` and sends each one `init`.
+    pub fn start<F, Fut>(self, make_node: F) -> Simulation
+    where
+        F: Fn(actors::RuntimeBuilder) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let node_ids: Vec<_> = (1..=self.nodes).map(|i| format!("n{i}")).collect();
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let (client_tx, client_messages) = mpsc::unbounded_channel();
+
+        let mut inputs = HashMap::new();
+        let mut nodes = Vec::new();
+        for id in &node_ids {
+            let (node_end, network_end) = io::duplex(PIPE_CAPACITY);
+            let runtime = actors::Runtime::with_transport(node_end)
+                .clock(self.clock.clone())
+                .trace(None)
+                .faults(Vec::new())
+                .fault_control(false);
+            nodes.push(tokio::spawn(make_node(runtime)));
+
+            let (output, input) = io::split(network_end);
+            inputs.insert(id.clone(), input);
+
+            let events = events.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(output).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if events.send(Event::Line(line)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let mut network = Network {
+            rng: Rng::new(self.seed),
+            clock: self.clock,
+            faults: self.faults,
+            inputs,
+            services: self.services,
+            components: HashMap::new(),
+            in_flight: BinaryHeap::new(),
+            next_seq: 0,
+            client_tx,
+            delivered: Vec::new(),
+        };
+
+        // init goes out first and without faults, like Maelstrom waits for
+        // every node to be up before starting the workload
+        for (i, id) in node_ids.iter().enumerate() {
+            let mut init = maelstrom_protocol::Message::new(
+                INIT_CLIENT.to_string(),
+                id.clone(),
+                maelstrom_protocol::Handshake::Init(maelstrom_protocol::InitPayload {
+                    node_id: id.clone(),
+                    node_ids: node_ids.to_vec(),
+                }),
+            );
+            init.body.id = Some(i + 1);
+            network.enqueue(
+                network.clock.now(),
+                serde_json::to_string(&init).expect("could not serialize init"),
+            );
+        }
+
+        Simulation {
+            node_ids,
+            events,
+            client_messages,
+            client_log: Vec::new(),
+            next_id: 0,
+            network: tokio::spawn(network.run(events_rx)),
+            nodes,
+        }
+    }
+}
+
+/// Owns the nodes' inputs and decides what reaches them, and when.
+struct Network {
+    rng: Rng,
+    clock: Arc<dyn actors::Clock>,
+    faults: Faults,
+    inputs: HashMap<String, WriteHalf<DuplexStream>>,
+    services: HashMap<String, KvStore>,
+    /// The partition each node is in. Empty when the network is whole.
+    components: HashMap<String, usize>,
+    in_flight: BinaryHeap<Reverse<(Instant, u64, String)>>,
+    next_seq: u64,
+    client_tx: mpsc::UnboundedSender<maelstrom_protocol::Envelope>,
+    delivered: Vec<String>,
+}
+
+impl Network {
+    async fn run(mut self, mut events: mpsc::UnboundedReceiver<Event>) -> Vec<String> {
+        loop {
+            let next = self.in_flight.peek().map(|Reverse((at, _, _))| *at);
+
+            tokio::select! {
+                biased;
+
+                _ = self.clock.sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                    let Reverse((_, _, line)) = self.in_flight.pop().expect("peeked message is gone");
+                    self.deliver(line).await;
+                }
+                event = events.recv() => match event {
+                    Some(Event::Line(line)) => self.submit(line),
+                    Some(Event::Partition(components)) => self.components = components,
+                    Some(Event::Shutdown) | None => break,
+                },
+            }
+        }
+
+        // the read halves are still around, so the nodes only see the end of
+        // their input once it is shut down
+        for input in self.inputs.values_mut() {
+            let _ = input.shutdown().await;
+        }
+
+        self.delivered
+    }
+
+    /// Decides whether, how often and when a message will arrive.
+    fn submit(&mut self, line: String) {
+        let envelope = match serde_json::from_str::<maelstrom_protocol::Envelope>(&line) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                eprintln!("malformed message: {e}: {line}");
+                return;
+            }
+        };
+
+        let mut copies = 1;
+        if self.is_node(&envelope.src) && self.is_node(&envelope.dst) {
+            if !self.can_reach(&envelope.src, &envelope.dst) || self.rng.chance(self.faults.loss) {
+                return;
+            }
+            if self.rng.chance(self.faults.duplication) {
+                copies = 2;
+            }
+        }
+
+        for _ in 0..copies {
+            let delay = self
+                .rng
+                .duration(self.faults.min_latency, self.faults.max_latency);
+            self.enqueue(self.clock.now() + delay, line.clone());
+        }
+    }
+
+    fn enqueue(&mut self, at: Instant, line: String) {
+        self.next_seq += 1;
+        self.in_flight.push(Reverse((at, self.next_seq, line)));
+    }
+
+    async fn deliver(&mut self, line: String) {
+        let envelope = match serde_json::from_str::<maelstrom_protocol::Envelope>(&line) {
+            Ok(envelope) => envelope,
+            Err(_) => return,
+        };
+        self.delivered.push(line.clone());
+
+        match self.inputs.get_mut(&envelope.dst) {
+            Some(input) => {
+                let written = async {
+                    input.write_all(line.as_bytes()).await?;
+                    input.write_all(b"\n").await?;
+                    input.flush().await
+                };
+                if let Err(e) = written.await {
+                    eprintln!("could not deliver to {}: {e}", envelope.dst);
+                }
+            }
+            None if envelope.dst == INIT_CLIENT => {}
+            None if self.services.contains_key(&envelope.dst) => self.serve(envelope),
+            None => {
+                let _ = self.client_tx.send(envelope);
+            }
+        }
+    }
+
+    /// Answers a request to a service, sending the reply back over the network.
+    fn serve(&mut self, envelope: maelstrom_protocol::Envelope) {
+        let service = self
+            .services
+            .get_mut(&envelope.dst)
+            .expect("service is registered");
+
+        let reply = match envelope.parse() {
+            Ok(request) => service.handle(&request),
+            Err(e) => {
+                eprintln!("malformed service request: {e}");
+                return;
+            }
+        };
+
+        if let Some(reply) = reply {
+            self.submit(serde_json::to_string(&reply).expect("could not serialize reply"));
+        }
+    }
+
+    fn is_node(&self, id: &str) -> bool {
+        self.inputs.contains_key(id)
+    }
+
+    fn can_reach(&self, src: &str, dst: &str) -> bool {
+        self.components.is_empty() || self.components.get(src) == self.components.get(dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[serde(tag = "type")]
+    enum Payload {
+        Broadcast { message: u64 },
+        BroadcastOk,
+        Gossip { message: u64 },
+        Read,
+        ReadOk { messages: Vec<u64> },
+    }
+
+    impl maelstrom_protocol::Payload for Payload {}
+
+    /// Forwards each broadcast to every peer and reads back what arrived,
+    /// in the order it did.
+    struct Gossip {
+        node: actors::NodeContext,
+        messages: Vec<u64>,
+    }
+
+    impl xtra::Actor for Gossip {}
+
+    #[async_trait::async_trait]
+    impl xtra::Handler<actors::Input<Payload>> for Gossip {
+        async fn handle(
+            &mut self,
+            actors::Input(message, cx): actors::Input<Payload>,
+            _ctx: &mut xtra::Context<Self>,
+        ) {
+            match message.body.payload {
+                Payload::Broadcast { message } => {
+                    self.messages.push(message);
+                    for peer in self.node.peers() {
+                        cx.send(peer.clone(), Payload::Gossip { message });
+                    }
+                    cx.reply(Payload::BroadcastOk);
+                }
+                Payload::Gossip { message } => self.messages.push(message),
+                Payload::Read => cx.reply(Payload::ReadOk {
+                    messages: self.messages.clone(),
+                }),
+                Payload::BroadcastOk | Payload::ReadOk { .. } => {}
+            }
+        }
+    }
+
+    async fn run(seed: u64) -> Vec<String> {
+        let mut sim = Simulation::builder(seed)
+            .nodes(5)
+            .latency(Duration::from_millis(1), Duration::from_millis(50))
+            .loss(0.2)
+            .duplication(0.2)
+            .start(|runtime| async move {
+                runtime
+                    .node(|node, _| Gossip {
+                        node,
+                        messages: Vec::new(),
+                    })
+                    .run()
+                    .await
+            });
+
+        for message in 0..20 {
+            let node = format!("n{}", message % 5 + 1);
+            sim.request("c1", &node, Payload::Broadcast { message });
+        }
+        for _ in 0..20 {
+            sim.recv().await.expect("broadcast is answered");
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        for node in sim.node_ids().to_vec() {
+            sim.request("c1", &node, Payload::Read);
+            sim.recv().await.expect("read is answered");
+        }
+
+        sim.shutdown().await
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn same_seed_gives_same_run() {
+        let first = run(42).await;
+        assert!(first.iter().any(|line| line.contains("gossip")));
+
+        for _ in 0..3 {
+            assert_eq!(run(42).await, first);
+        }
+        assert_ne!(run(7).await, first);
+    }
+}
@@ -0,0 +1,283 @@
+use super::Rng;
+use crate::{actors::service::KvPayload, maelstrom_protocol};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// How far back in time a last-write-wins write may be stamped, in writes.
+const LWW_SKEW: u64 = 3;
+
+/// The guarantees a [`KvStore`] gives, matching Maelstrom's services.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    /// Every operation sees the latest write, like `lin-kv`.
+    Linearizable,
+    /// Each client sees writes in order, but reads may be stale, like `seq-kv`.
+    Sequential,
+    /// Writes are ordered by a skewed timestamp, so some are lost, like
+    /// `lww-kv`.
+    LastWriteWins,
+}
+
+/// An in-memory key-value service answering [`KvPayload`] requests.
+pub struct KvStore {
+    consistency: Consistency,
+    rng: Rng,
+    /// The latest timestamp handed out.
+    now: u64,
+    /// Every value each key has had, ordered by timestamp.
+    keys: HashMap<String, Vec<(u64, Value)>>,
+    /// The timestamp each client has seen up to, so its reads never go back.
+    seen: HashMap<String, u64>,
+}
+
+impl KvStore {
+    pub fn new(consistency: Consistency, seed: u64) -> Self {
+        Self {
+            consistency,
+            rng: Rng::new(seed),
+            now: 0,
+            keys: HashMap::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    pub fn lin_kv(seed: u64) -> Self {
+        Self::new(Consistency::Linearizable, seed)
+    }
+
+    pub fn seq_kv(seed: u64) -> Self {
+        Self::new(Consistency::Sequential, seed)
+    }
+
+    pub fn lww_kv(seed: u64) -> Self {
+        Self::new(Consistency::LastWriteWins, seed)
+    }
+
+    /// `seq-kv`, `lin-kv` and `lww-kv` by name, each seeded differently from
+    /// `seed`.
+    pub fn maelstrom(seed: u64) -> HashMap<String, Self> {
+        let mut seeds = Rng::new(seed);
+        HashMap::from([
+            ("seq-kv".to_string(), Self::seq_kv(seeds.next_u64())),
+            ("lin-kv".to_string(), Self::lin_kv(seeds.next_u64())),
+            ("lww-kv".to_string(), Self::lww_kv(seeds.next_u64())),
+        ])
+    }
+
+    /// Answers a request. Anything that is not a request gets no answer.
+    pub fn handle(
+        &mut self,
+        message: &maelstrom_protocol::Message<KvPayload>,
+    ) -> Option<maelstrom_protocol::Message<maelstrom_protocol::Reply<KvPayload>>> {
+        message.body.id?;
+
+        let reply = match &message.body.payload {
+            KvPayload::Read { key } => self
+                .read(&message.src, key)
+                .map(|value| KvPayload::ReadOk { value }),
+            KvPayload::Write { key, value } => {
+                self.write(&message.src, key, value.clone());
+                Ok(KvPayload::WriteOk)
+            }
+            KvPayload::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => self
+                .cas(&message.src, key, from, to, *create_if_not_exists)
+                .map(|_| KvPayload::CasOk),
+            KvPayload::ReadOk { .. }
+            | KvPayload::WriteOk
+            | KvPayload::CasOk
+            | KvPayload::Unknown => Err(maelstrom_protocol::Error::new(
+                maelstrom_protocol::ErrorCode::NotSupported,
+                "not a key-value request",
+            )),
+        };
+
+        Some(match reply {
+            Ok(payload) => message.make_response(payload),
+            Err(error) => message.make_error_response(error),
+        })
+    }
+
+    fn read(&mut self, client: &str, key: &str) -> Result<Value, maelstrom_protocol::Error> {
+        let at = match self.consistency {
+            Consistency::Linearizable | Consistency::LastWriteWins => self.now,
+            Consistency::Sequential => {
+                let seen = self.seen.get(client).copied().unwrap_or_default();
+                let at = seen + self.rng.next_u64() % (self.now - seen + 1);
+                self.seen.insert(client.to_string(), at);
+                at
+            }
+        };
+
+        self.value_at(key, at).cloned().ok_or_else(|| {
+            maelstrom_protocol::Error::new(
+                maelstrom_protocol::ErrorCode::KeyDoesNotExist,
+                format!("key {key} does not exist"),
+            )
+        })
+    }
+
+    fn write(&mut self, client: &str, key: &str, value: Value) {
+        self.now += 1;
+
+        let at = match self.consistency {
+            Consistency::Linearizable | Consistency::Sequential => self.now,
+            Consistency::LastWriteWins => self
+                .now
+                .saturating_sub(self.rng.next_u64() % (LWW_SKEW + 1)),
+        };
+
+        let versions = self.keys.entry(key.to_string()).or_default();
+        let i = versions.partition_point(|(t, _)| *t <= at);
+        versions.insert(i, (at, value));
+
+        self.seen.insert(client.to_string(), self.now);
+    }
+
+    fn cas(
+        &mut self,
+        client: &str,
+        key: &str,
+        from: &Value,
+        to: &Value,
+        create_if_not_exists: bool,
+    ) -> Result<(), maelstrom_protocol::Error> {
+        // cas always acts on the latest value, even where reads may be stale,
+        // so the client has seen it whether or not the cas goes through
+        self.seen.insert(client.to_string(), self.now);
+        match self.value_at(key, self.now) {
+            Some(current) if current == from => {}
+            Some(current) => {
+                return Err(maelstrom_protocol::Error::new(
+                    maelstrom_protocol::ErrorCode::PreconditionFailed,
+                    format!("expected {from}, but had {current}"),
+                ))
+            }
+            None if create_if_not_exists => {}
+            None => {
+                return Err(maelstrom_protocol::Error::new(
+                    maelstrom_protocol::ErrorCode::KeyDoesNotExist,
+                    format!("key {key} does not exist"),
+                ))
+            }
+        }
+
+        self.write(client, key, to.clone());
+        Ok(())
+    }
+
+    /// The value `key` had at timestamp `at`.
+    fn value_at(&self, key: &str, at: u64) -> Option<&Value> {
+        let versions = self.keys.get(key)?;
+        let i = versions.partition_point(|(t, _)| *t <= at);
+        versions[..i].last().map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(
+        store: &mut KvStore,
+        client: &str,
+        payload: KvPayload,
+    ) -> Result<KvPayload, maelstrom_protocol::Error> {
+        let mut message =
+            maelstrom_protocol::Message::new(client.to_string(), "seq-kv".to_string(), payload);
+        message.body.id = Some(1);
+
+        let reply = store.handle(&message).expect("requests are answered");
+        reply.body.payload.into_result()
+    }
+
+    fn read(store: &mut KvStore, client: &str) -> Value {
+        match request(store, client, KvPayload::Read { key: "k".into() }) {
+            Ok(KvPayload::ReadOk { value }) => value,
+            other => panic!("expected read_ok, got {other:?}"),
+        }
+    }
+
+    fn write(store: &mut KvStore, client: &str, value: u64) {
+        let payload = KvPayload::Write {
+            key: "k".into(),
+            value: json!(value),
+        };
+        request(store, client, payload).expect("write succeeds");
+    }
+
+    fn cas(
+        store: &mut KvStore,
+        client: &str,
+        from: u64,
+        to: u64,
+    ) -> Result<KvPayload, maelstrom_protocol::Error> {
+        let payload = KvPayload::Cas {
+            key: "k".into(),
+            from: json!(from),
+            to: json!(to),
+            create_if_not_exists: false,
+        };
+        request(store, client, payload)
+    }
+
+    #[test]
+    fn sequential_reads_do_not_go_back_after_cas() {
+        for seed in 0..50 {
+            let mut store = KvStore::seq_kv(seed);
+            for value in 0..10 {
+                write(&mut store, "a", value);
+            }
+
+            cas(&mut store, "b", 9, 10).expect("cas succeeds");
+            assert_eq!(read(&mut store, "b"), json!(10), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn sequential_reads_do_not_go_back_after_failed_cas() {
+        for seed in 0..50 {
+            let mut store = KvStore::seq_kv(seed);
+            for value in 0..10 {
+                write(&mut store, "a", value);
+            }
+
+            let error = cas(&mut store, "b", 3, 10).expect_err("cas fails");
+            assert_eq!(
+                error.code,
+                maelstrom_protocol::ErrorCode::PreconditionFailed
+            );
+            assert_eq!(read(&mut store, "b"), json!(9), "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn sequential_reads_may_be_stale_for_other_clients() {
+        let stale = (0..50).any(|seed| {
+            let mut store = KvStore::seq_kv(seed);
+            for value in 0..10 {
+                write(&mut store, "a", value);
+            }
+            read(&mut store, "b") != json!(9)
+        });
+        assert!(stale);
+    }
+
+    #[test]
+    fn maelstrom_stores_are_seeded_differently() {
+        let stores = KvStore::maelstrom(42);
+        let mut draws: Vec<_> = stores
+            .values()
+            .map(|store| store.rng.clone().next_u64())
+            .collect();
+        draws.sort();
+        draws.dedup();
+        assert_eq!(draws.len(), 3);
+        assert_eq!(stores["seq-kv"].consistency, Consistency::Sequential);
+    }
+}
@@ -0,0 +1,476 @@
+//! Drives a single node actor by hand, for testing handlers without Maelstrom.
+//!
+//! The node runs behind the real runtime, so replies, RPCs and service calls
+//! take the same path they do under Maelstrom. Everything the node writes is
+//! recorded and can be asserted on. Tracing and faults are off whatever the
+//! environment says.
+
+use crate::{actors, maelstrom_protocol, sim};
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::{
+    io::{
+        self, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf,
+    },
+    sync::oneshot,
+    task,
+    time::Instant,
+};
+
+/// How long an assertion waits for a matching message.
+pub const TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// How long to wait for the output of a tick before sending the next one.
+const TICK_GRACE: Duration = Duration::from_millis(100);
+
+/// The client requests are sent from.
+pub const CLIENT: &str = "c1";
+
+/// A message the node wrote, with its payload left as JSON.
+pub type Output = maelstrom_protocol::Message<Value>;
+
+pub struct TestNode<A: xtra::Actor> {
+    node: xtra::WeakAddress<A>,
+    node_id: String,
+    input: WriteHalf<DuplexStream>,
+    output: Lines<BufReader<ReadHalf<DuplexStream>>>,
+    outputs: Vec<Output>,
+    next_id: usize,
+    stopped: task::JoinHandle<()>,
+}
+
+impl<A: xtra::Actor> TestNode<A> {
+    /// Starts a node as `node_id` in a cluster of `node_ids` and waits for it
+    /// to finish the `init` handshake.
+    pub async fn start<P, F>(node_id: &str, node_ids: &[&str], make_node: F) -> Self
+    where
+        A: xtra::Handler<actors::Input<P>>,
+        P: maelstrom_protocol::Payload + 'static,
+        F: FnOnce(actors::NodeContext, &actors::Runtime) -> A + Send + 'static,
+    {
+        let (node_end, kit_end) = io::duplex(1 << 20);
+        let (output, mut input) = io::split(kit_end);
+        let (node_tx, node_rx) = oneshot::channel();
+
+        let stopped = tokio::spawn(
+            actors::Runtime::with_transport(node_end)
+                .trace(None)
+                .faults(Vec::new())
+                .fault_control(false)
+                .node(make_node)
+                .on_start(move |node| {
+                    let _ = node_tx.send(node);
+                })
+                .run(),
+        );
+
+        let mut init = maelstrom_protocol::Message::new(
+            sim::INIT_CLIENT.to_string(),
+            node_id.to_string(),
+            maelstrom_protocol::Handshake::Init(maelstrom_protocol::InitPayload {
+                node_id: node_id.to_string(),
+                node_ids: node_ids.iter().map(|n| n.to_string()).collect(),
+            }),
+        );
+        init.body.id = Some(0);
+        write_line(&mut input, &init).await;
+
+        let node = tokio::time::timeout(TIMEOUT, node_rx)
+            .await
+            .expect("node did not start in time")
+            .expect("node did not start");
+
+        let mut output = BufReader::new(output).lines();
+        let init_ok = output
+            .next_line()
+            .await
+            .expect("could not read init_ok")
+            .expect("node stopped before init_ok");
+        assert!(
+            init_ok.contains("init_ok"),
+            "expected init_ok, got {init_ok}"
+        );
+
+        Self {
+            node,
+            node_id: node_id.to_string(),
+            input,
+            output,
+            outputs: Vec::new(),
+            next_id: 0,
+            stopped,
+        }
+    }
+
+    /// Everything the node has written so far, apart from `init_ok`.
+    pub fn outputs(&self) -> &[Output] {
+        &self.outputs
+    }
+
+    /// Feeds the node a message, as is.
+    pub async fn send<P: Serialize>(&mut self, message: &maelstrom_protocol::Message<P>) {
+        write_line(&mut self.input, message).await;
+    }
+
+    /// Feeds the node a raw line of JSON.
+    pub async fn send_line(&mut self, line: &str) {
+        self.input
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .expect("could not write to node");
+    }
+
+    /// Sends a request from `src` and returns the `msg_id` it was given.
+    pub async fn request_from<P: Serialize>(&mut self, src: &str, payload: P) -> usize {
+        self.next_id += 1;
+
+        let mut message =
+            maelstrom_protocol::Message::new(src.to_string(), self.node_id.clone(), payload);
+        message.body.id = Some(self.next_id);
+        self.send(&message).await;
+
+        self.next_id
+    }
+
+    /// Sends a request from [`CLIENT`] and returns the `msg_id` it was given.
+    pub async fn request<P: Serialize>(&mut self, payload: P) -> usize {
+        self.request_from(CLIENT, payload).await
+    }
+
+    /// Sends the node a message directly, as a periodic task would, and waits
+    /// for it to be handled.
+    pub async fn tick<M>(&mut self, message: M)
+    where
+        A: xtra::Handler<M>,
+        M: xtra::Message<Result = ()>,
+    {
+        self.node
+            .send(message)
+            .await
+            .expect("node stopped before the tick");
+    }
+
+    /// Waits for a message matching `predicate` and returns it. Messages
+    /// recorded earlier count too.
+    pub async fn expect(
+        &mut self,
+        description: &str,
+        predicate: impl Fn(&Output) -> bool,
+    ) -> Output {
+        match self.wait_for(0, TIMEOUT, &predicate).await {
+            Some(message) => message,
+            None => panic!("expected {description}, got {}", describe(&self.outputs)),
+        }
+    }
+
+    /// Asserts the node answered `msg_id` with a message of type `kind`.
+    pub async fn assert_replied(&mut self, msg_id: usize, kind: &str) -> Output {
+        self.expect(&format!("{kind} in reply to msg {msg_id}"), |m| {
+            m.body.in_reply_to == Some(msg_id) && kind_of(m) == Some(kind)
+        })
+        .await
+    }
+
+    /// Asserts the node sent a message of type `kind` to `dest`.
+    pub async fn assert_sent(&mut self, dest: &str, kind: &str) -> Output {
+        self.expect(&format!("{kind} to {dest}"), |m| {
+            m.dst == dest && kind_of(m) == Some(kind)
+        })
+        .await
+    }
+
+    /// Asserts the node sends a message of type `kind` to `dest` within
+    /// `ticks` calls of `make_tick`. Only messages sent from now on count.
+    pub async fn assert_sent_within_ticks<M>(
+        &mut self,
+        ticks: usize,
+        make_tick: impl Fn() -> M,
+        dest: &str,
+        kind: &str,
+    ) -> Output
+    where
+        A: xtra::Handler<M>,
+        M: xtra::Message<Result = ()>,
+    {
+        let from = self.outputs.len();
+        let predicate = |m: &Output| m.dst == dest && kind_of(m) == Some(kind);
+
+        for _ in 0..ticks {
+            self.tick(make_tick()).await;
+            if let Some(message) = self.wait_for(from, TICK_GRACE, &predicate).await {
+                return message;
+            }
+        }
+
+        panic!(
+            "expected {kind} to {dest} within {ticks} ticks, got {}",
+            describe(&self.outputs[from..])
+        );
+    }
+
+    /// Closes the node's input and waits for it to stop, recording whatever
+    /// it still writes.
+    pub async fn stop(mut self) -> Vec<Output> {
+        let _ = self.input.shutdown().await;
+        while let Ok(Some(line)) = self.output.next_line().await {
+            self.record(&line);
+        }
+        let _ = self.stopped.await;
+
+        self.outputs
+    }
+
+    /// Looks for a match among the outputs from index `from` on, reading new
+    /// ones for up to `timeout`.
+    async fn wait_for(
+        &mut self,
+        from: usize,
+        timeout: Duration,
+        predicate: &impl Fn(&Output) -> bool,
+    ) -> Option<Output> {
+        if let Some(message) = self.outputs[from..].iter().find(|m| predicate(m)) {
+            return Some(message.clone());
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let line = match tokio::time::timeout_at(deadline, self.output.next_line()).await {
+                Ok(Ok(Some(line))) => line,
+                _ => return None,
+            };
+
+            if let Some(message) = self.record(&line) {
+                if predicate(&message) {
+                    return Some(message);
+                }
+            }
+        }
+    }
+
+    fn record(&mut self, line: &str) -> Option<Output> {
+        match serde_json::from_str::<Output>(line) {
+            Ok(message) => {
+                self.outputs.push(message.clone());
+                Some(message)
+            }
+            Err(e) => panic!("node wrote a malformed message: {e}: {line}"),
+        }
+    }
+}
+
+/// The `type` of a message's body.
+pub fn kind_of(message: &Output) -> Option<&str> {
+    message.body.payload.get("type")?.as_str()
+}
+
+async fn write_line<P: Serialize>(
+    input: &mut WriteHalf<DuplexStream>,
+    message: &maelstrom_protocol::Message<P>,
+) {
+    let mut line = serde_json::to_vec(message).expect("could not serialize message");
+    line.push(b'\n');
+    input
+        .write_all(&line)
+        .await
+        .expect("could not write to node");
+}
+
+fn describe(outputs: &[Output]) -> String {
+    match outputs {
+        [] => "nothing".to_string(),
+        _ => outputs
+            .iter()
+            .map(|m| serde_json::to_string(m).expect("could not serialize message"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[serde(tag = "type")]
+    enum Payload {
+        Echo {
+            echo: String,
+        },
+        EchoOk {
+            echo: String,
+        },
+        Broadcast {
+            message: u64,
+        },
+        BroadcastOk,
+        Gossip {
+            messages: Vec<u64>,
+        },
+        /// Asks `n2` to echo `echo` and answers with what it said.
+        Forward {
+            echo: String,
+        },
+        ForwardOk {
+            echo: String,
+        },
+    }
+
+    impl maelstrom_protocol::Payload for Payload {}
+
+    struct Node {
+        node: actors::NodeContext,
+        messages: Vec<u64>,
+        gossip: Option<actors::HandlerContext<Payload>>,
+    }
+
+    impl xtra::Actor for Node {}
+
+    struct Tick;
+
+    impl xtra::Message for Tick {
+        type Result = ();
+    }
+
+    #[async_trait::async_trait]
+    impl xtra::Handler<actors::Input<Payload>> for Node {
+        async fn handle(
+            &mut self,
+            actors::Input(message, cx): actors::Input<Payload>,
+            _ctx: &mut xtra::Context<Self>,
+        ) {
+            match message.body.payload {
+                Payload::Echo { echo } => cx.reply(Payload::EchoOk { echo }),
+                Payload::Broadcast { message } => {
+                    self.messages.push(message);
+                    cx.reply(Payload::BroadcastOk);
+                    self.gossip.replace(cx);
+                }
+                Payload::Forward { echo } => {
+                    tokio::spawn(async move {
+                        match cx.rpc("n2", Payload::Echo { echo }).await {
+                            Ok(reply) => match reply.body.payload {
+                                Payload::EchoOk { echo } => cx.reply(Payload::ForwardOk { echo }),
+                                _ => cx.reply_error(maelstrom_protocol::ErrorCode::Crash),
+                            },
+                            Err(error) => cx.reply_error(error),
+                        }
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl xtra::Handler<Tick> for Node {
+        async fn handle(&mut self, _: Tick, _ctx: &mut xtra::Context<Self>) {
+            let Some(cx) = &self.gossip else {
+                return;
+            };
+            for peer in self.node.peers() {
+                cx.send(
+                    peer.clone(),
+                    Payload::Gossip {
+                        messages: self.messages.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    async fn start() -> TestNode<Node> {
+        TestNode::start("n1", &["n1", "n2", "n3"], |node, _| Node {
+            node,
+            messages: Vec::new(),
+            gossip: None,
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn echo() {
+        let mut node = start().await;
+
+        let id = node
+            .request(Payload::Echo {
+                echo: "hello".to_string(),
+            })
+            .await;
+        let reply = node.assert_replied(id, "echo_ok").await;
+        assert_eq!(reply.dst, CLIENT);
+        assert_eq!(reply.body.payload["echo"], "hello");
+
+        let id = node.request(serde_json::json!({"type": "nope"})).await;
+        let reply = node.assert_replied(id, "error").await;
+        assert_eq!(reply.body.payload["code"], 10);
+    }
+
+    #[tokio::test]
+    async fn broadcast_gossips_to_every_peer() {
+        let mut node = start().await;
+
+        let id = node.request(Payload::Broadcast { message: 7 }).await;
+        node.assert_replied(id, "broadcast_ok").await;
+
+        let gossip = node
+            .assert_sent_within_ticks(3, || Tick, "n2", "gossip")
+            .await;
+        assert_eq!(gossip.body.payload["messages"], serde_json::json!([7]));
+        node.assert_sent("n3", "gossip").await;
+
+        let outputs = node.stop().await;
+        assert!(!outputs.iter().any(|m| m.dst == "n1"));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "expected gossip to n2 within 2 ticks")]
+    async fn no_gossip_before_a_broadcast() {
+        let mut node = start().await;
+        node.assert_sent_within_ticks(2, || Tick, "n2", "gossip")
+            .await;
+    }
+
+    #[tokio::test]
+    async fn rpc_is_answered() {
+        let mut node = start().await;
+
+        let id = node
+            .request(Payload::Forward {
+                echo: "hi".to_string(),
+            })
+            .await;
+        let request = node.assert_sent("n2", "echo").await;
+
+        let mut reply = maelstrom_protocol::Message::new(
+            "n2".to_string(),
+            "n1".to_string(),
+            Payload::EchoOk {
+                echo: "hi".to_string(),
+            },
+        );
+        reply.body.in_reply_to = request.body.id;
+        node.send(&reply).await;
+
+        let reply = node.assert_replied(id, "forward_ok").await;
+        assert_eq!(reply.body.payload["echo"], "hi");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rpc_times_out() {
+        let mut node = start().await;
+
+        let id = node
+            .request(Payload::Forward {
+                echo: "hi".to_string(),
+            })
+            .await;
+        node.assert_sent("n2", "echo").await;
+
+        tokio::time::sleep(actors::service::DEFAULT_TIMEOUT).await;
+        let reply = node.assert_replied(id, "error").await;
+        assert_eq!(reply.body.payload["code"], 0);
+    }
+}
@@ -0,0 +1,45 @@
+//! JSONL traces of everything a node read and wrote.
+//!
+//! Set [`TRACE_DIR_ENV`] to a directory, or call
+//! [`crate::actors::RuntimeBuilder::trace`], and each node writes
+//! `<dir>/<node id>.jsonl` with one [`TraceEntry`] per line.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{io, path::Path};
+
+mod diagram;
+mod recorder;
+mod replay;
+
+pub use diagram::*;
+pub(crate) use recorder::Tracer;
+pub use replay::*;
+
+/// The environment variable naming the directory traces are written to.
+pub const TRACE_DIR_ENV: &str = "GLOMERS_TRACE_DIR";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Microseconds since the Unix epoch.
+    pub time: u64,
+    pub direction: Direction,
+    /// The message, or the raw line as a string when it was not JSON.
+    pub message: Value,
+}
+
+/// Reads a trace written by a node.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<TraceEntry>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(io::Error::from))
+        .collect()
+}
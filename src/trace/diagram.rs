@@ -0,0 +1,413 @@
+use super::{Direction, TraceEntry};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+/// A message as it went from one participant to another.
+#[derive(Debug, Clone)]
+pub struct Flow {
+    /// When it was first seen, in microseconds since the Unix epoch.
+    pub time: u64,
+    pub src: String,
+    pub dest: String,
+    /// The body's `type`, or `?` when it had none.
+    pub kind: String,
+    pub message: Value,
+    /// Sent to a traced node whose trace never shows it arriving.
+    pub lost: bool,
+}
+
+impl Flow {
+    fn new(entry: &TraceEntry, lost: bool) -> Option<Self> {
+        let (src, dest) = endpoints(entry)?;
+        let kind = entry
+            .message
+            .pointer("/body/type")
+            .and_then(Value::as_str)
+            .unwrap_or("?");
+
+        Some(Self {
+            time: entry.time,
+            src: src.to_string(),
+            dest: dest.to_string(),
+            kind: kind.to_string(),
+            message: entry.message.clone(),
+            lost,
+        })
+    }
+}
+
+/// Merges the traces of several nodes into one list of messages, in time
+/// order. A message between two traced nodes shows up in both traces: each
+/// send is paired with a receive on the other side, so copies are all kept
+/// and sends that never arrived are marked lost. Messages to and from clients
+/// and services come from the node's side alone.
+pub fn flows<'a>(traces: impl IntoIterator<Item = &'a [TraceEntry]>) -> Vec<Flow> {
+    let traces: Vec<&[TraceEntry]> = traces.into_iter().collect();
+    let traced: HashSet<&str> = traces.iter().filter_map(|trace| owner(trace)).collect();
+
+    let mut sends = Vec::new();
+    let mut receives: HashMap<String, VecDeque<&TraceEntry>> = HashMap::new();
+    let mut flows = Vec::new();
+    for entry in traces.iter().copied().flatten() {
+        let Some((src, _)) = endpoints(entry) else {
+            continue;
+        };
+        match entry.direction {
+            Direction::Out => sends.push(entry),
+            Direction::In if traced.contains(src) => receives
+                .entry(pairing_key(entry))
+                .or_default()
+                .push_back(entry),
+            Direction::In => flows.extend(Flow::new(entry, false)),
+        }
+    }
+
+    sends.sort_by_key(|entry| entry.time);
+    for received in receives.values_mut() {
+        received.make_contiguous().sort_by_key(|entry| entry.time);
+    }
+    for send in sends {
+        let (_, dest) = endpoints(send).expect("sends have endpoints");
+        let received = receives
+            .get_mut(&pairing_key(send))
+            .and_then(VecDeque::pop_front);
+        flows.extend(Flow::new(send, received.is_none() && traced.contains(dest)));
+    }
+
+    // receives whose send is missing from the sender's trace
+    flows.extend(
+        receives
+            .into_values()
+            .flatten()
+            .filter_map(|entry| Flow::new(entry, false)),
+    );
+
+    flows.sort_by_key(|flow| flow.time);
+    flows
+}
+
+/// The node a trace belongs to: the receiver of what it read, or the sender
+/// of what it wrote.
+fn owner(trace: &[TraceEntry]) -> Option<&str> {
+    trace.iter().find_map(|entry| {
+        let (src, dest) = endpoints(entry)?;
+        match entry.direction {
+            Direction::In => Some(dest),
+            Direction::Out => Some(src),
+        }
+    })
+}
+
+fn endpoints(entry: &TraceEntry) -> Option<(&str, &str)> {
+    let src = entry.message.get("src").and_then(Value::as_str)?;
+    let dest = entry.message.get("dest").and_then(Value::as_str)?;
+    Some((src, dest))
+}
+
+/// What a send and its receive have in common. Anything outside of `src`,
+/// `dest` and `body`, like the `id` Maelstrom adds in transit, is ignored.
+fn pairing_key(entry: &TraceEntry) -> String {
+    let message = &entry.message;
+    serde_json::json!([message["src"], message["dest"], message["body"]]).to_string()
+}
+
+/// A Mermaid sequence diagram of the messages, with participants in the order
+/// they first appear. Lost messages end in a cross.
+pub fn mermaid(flows: &[Flow]) -> String {
+    let mut out = String::from("sequenceDiagram\n");
+    for participant in participants(flows) {
+        out += &format!("    participant {} as {participant}\n", alias(&participant));
+    }
+    for flow in flows {
+        let arrow = if flow.lost { "-x" } else { "->>" };
+        out += &format!(
+            "    {}{arrow}{}: {}\n",
+            alias(&flow.src),
+            alias(&flow.dest),
+            label(flow)
+        );
+    }
+    out
+}
+
+/// A Graphviz graph of who sends what to whom, one edge per message type
+/// labelled with how many were sent and how many of those were lost.
+pub fn graphviz(flows: &[Flow]) -> String {
+    let mut edges: BTreeMap<(&str, &str, &str), (usize, usize)> = BTreeMap::new();
+    for flow in flows {
+        let (sent, lost) = edges
+            .entry((&flow.src, &flow.dest, &flow.kind))
+            .or_default();
+        *sent += 1;
+        *lost += usize::from(flow.lost);
+    }
+
+    let mut out = String::from("digraph messages {\n");
+    for participant in participants(flows) {
+        out += &format!("    {};\n", quote(&participant));
+    }
+    for ((src, dest, kind), (sent, lost)) in edges {
+        let label = match lost {
+            0 => format!("{kind} x{sent}"),
+            lost => format!("{kind} x{sent}, {lost} lost"),
+        };
+        out += &format!(
+            "    {} -> {} [label={}];\n",
+            quote(src),
+            quote(dest),
+            quote(&label)
+        );
+    }
+    out += "}\n";
+    out
+}
+
+/// A Graphviz string literal.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// How many messages of each type went between each pair of participants.
+#[derive(Debug, Clone, Default)]
+pub struct TrafficMatrix {
+    pub participants: Vec<String>,
+    /// `(src, dest)` counts per message type, lost messages included.
+    pub counts: BTreeMap<String, BTreeMap<(String, String), usize>>,
+    /// How many messages of each type were lost.
+    pub lost: BTreeMap<String, usize>,
+}
+
+impl TrafficMatrix {
+    pub fn new(flows: &[Flow]) -> Self {
+        let mut counts: BTreeMap<String, BTreeMap<(String, String), usize>> = BTreeMap::new();
+        let mut lost: BTreeMap<String, usize> = BTreeMap::new();
+        for flow in flows {
+            *counts
+                .entry(flow.kind.clone())
+                .or_default()
+                .entry((flow.src.clone(), flow.dest.clone()))
+                .or_default() += 1;
+            if flow.lost {
+                *lost.entry(flow.kind.clone()).or_default() += 1;
+            }
+        }
+
+        Self {
+            participants: participants(flows),
+            counts,
+            lost,
+        }
+    }
+}
+
+impl std::fmt::Display for TrafficMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self
+            .participants
+            .iter()
+            .map(String::len)
+            .max()
+            .unwrap_or_default()
+            .max(8);
+
+        for (kind, counts) in &self.counts {
+            let total: usize = counts.values().sum();
+            match self.lost.get(kind) {
+                Some(lost) => writeln!(f, "{kind} ({total} sent, {lost} lost)")?,
+                None => writeln!(f, "{kind} ({total} sent)")?,
+            }
+
+            // only the rows and columns that saw this type
+            let srcs: BTreeSet<_> = counts.keys().map(|(src, _)| src).collect();
+            let dests: BTreeSet<_> = counts.keys().map(|(_, dest)| dest).collect();
+            let srcs = self.participants.iter().filter(|p| srcs.contains(p));
+            let dests: Vec<_> = self
+                .participants
+                .iter()
+                .filter(|p| dests.contains(p))
+                .collect();
+
+            write!(f, "{:>width$}", "from\\to")?;
+            for dest in &dests {
+                write!(f, " {dest:>width$}")?;
+            }
+            writeln!(f)?;
+
+            for src in srcs {
+                write!(f, "{src:>width$}")?;
+                for dest in &dests {
+                    match counts.get(&(src.clone(), dest.to_string())) {
+                        Some(count) => write!(f, " {count:>width$}")?,
+                        None => write!(f, " {:>width$}", ".")?,
+                    }
+                }
+                writeln!(f)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Everyone who sent or received a message, in order of appearance.
+fn participants(flows: &[Flow]) -> Vec<String> {
+    let mut participants: Vec<String> = Vec::new();
+    for flow in flows {
+        for id in [&flow.src, &flow.dest] {
+            if !participants.contains(id) {
+                participants.push(id.clone());
+            }
+        }
+    }
+    participants
+}
+
+/// Mermaid ids can't hold dashes, as in `seq-kv`.
+fn alias(participant: &str) -> String {
+    participant
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// The message type, with the ids that tie requests to replies.
+fn label(flow: &Flow) -> String {
+    let body = flow.message.get("body");
+    let id = |key| body.and_then(|body| body.get(key)).and_then(Value::as_u64);
+
+    match (id("msg_id"), id("in_reply_to")) {
+        (Some(msg_id), Some(in_reply_to)) => format!("{} id={msg_id} re={in_reply_to}", flow.kind),
+        (Some(msg_id), None) => format!("{} id={msg_id}", flow.kind),
+        (None, Some(in_reply_to)) => format!("{} re={in_reply_to}", flow.kind),
+        (None, None) => flow.kind.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(time: u64, direction: Direction, message: Value) -> TraceEntry {
+        TraceEntry {
+            time,
+            direction,
+            message,
+        }
+    }
+
+    fn message(src: &str, dest: &str, kind: &str, msg_id: u64) -> Value {
+        json!({"src": src, "dest": dest, "body": {"type": kind, "msg_id": msg_id}})
+    }
+
+    /// n1 sends gossip to n2 twice, byte for byte, and once to n3, which
+    /// never gets it.
+    fn traces() -> Vec<Vec<TraceEntry>> {
+        let gossip = message("n1", "n2", "gossip", 1);
+        let mut delivered = gossip.clone();
+        delivered["id"] = json!(7);
+
+        vec![
+            vec![
+                entry(10, Direction::In, message("c1", "n1", "read", 1)),
+                entry(20, Direction::Out, gossip.clone()),
+                entry(21, Direction::Out, gossip),
+                entry(22, Direction::Out, message("n1", "n3", "gossip", 2)),
+            ],
+            vec![
+                entry(30, Direction::In, delivered.clone()),
+                entry(31, Direction::In, delivered),
+            ],
+            vec![entry(40, Direction::Out, message("n3", "c1", "read_ok", 1))],
+        ]
+    }
+
+    fn summary(flows: &[Flow]) -> Vec<(u64, &str, &str, &str, bool)> {
+        flows
+            .iter()
+            .map(|f| (f.time, &*f.src, &*f.dest, &*f.kind, f.lost))
+            .collect()
+    }
+
+    #[test]
+    fn sends_are_paired_with_receives() {
+        let traces = traces();
+        let flows = flows(traces.iter().map(Vec::as_slice));
+
+        assert_eq!(
+            summary(&flows),
+            [
+                (10, "c1", "n1", "read", false),
+                (20, "n1", "n2", "gossip", false),
+                (21, "n1", "n2", "gossip", false),
+                (22, "n1", "n3", "gossip", true),
+                (40, "n3", "c1", "read_ok", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn receives_without_a_traced_send_are_kept() {
+        let traces = traces();
+        let flows = flows(traces[1..].iter().map(Vec::as_slice));
+
+        // without n1's trace, n1 is just another client
+        assert_eq!(
+            summary(&flows),
+            [
+                (30, "n1", "n2", "gossip", false),
+                (31, "n1", "n2", "gossip", false),
+                (40, "n3", "c1", "read_ok", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn mermaid_crosses_out_lost_messages() {
+        let traces = traces();
+        let flows = flows(traces.iter().map(Vec::as_slice));
+
+        assert_eq!(
+            mermaid(&flows[1..4]),
+            "sequenceDiagram\n\
+             \x20   participant n1 as n1\n\
+             \x20   participant n2 as n2\n\
+             \x20   participant n3 as n3\n\
+             \x20   n1->>n2: gossip id=1\n\
+             \x20   n1->>n2: gossip id=1\n\
+             \x20   n1-xn3: gossip id=2\n"
+        );
+    }
+
+    #[test]
+    fn graphviz_counts_and_escapes() {
+        let traces = traces();
+        let mut flows = flows(traces.iter().map(Vec::as_slice));
+        flows[0].kind = r#"say "hi" \o/"#.to_string();
+        flows.truncate(4);
+
+        assert_eq!(
+            graphviz(&flows),
+            "digraph messages {\n\
+             \x20   \"c1\";\n\
+             \x20   \"n1\";\n\
+             \x20   \"n2\";\n\
+             \x20   \"n3\";\n\
+             \x20   \"c1\" -> \"n1\" [label=\"say \\\"hi\\\" \\\\o/ x1\"];\n\
+             \x20   \"n1\" -> \"n2\" [label=\"gossip x2\"];\n\
+             \x20   \"n1\" -> \"n3\" [label=\"gossip x1, 1 lost\"];\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn matrix_reports_lost_messages() {
+        let traces = traces();
+        let matrix = TrafficMatrix::new(&flows(traces.iter().map(Vec::as_slice)));
+
+        assert_eq!(matrix.counts["gossip"].values().sum::<usize>(), 3);
+        assert_eq!(matrix.lost["gossip"], 1);
+        assert!(!matrix.lost.contains_key("read"));
+        assert!(matrix.to_string().contains("gossip (3 sent, 1 lost)"));
+    }
+}
@@ -0,0 +1,106 @@
+use super::{Direction, TraceEntry};
+use serde_json::Value;
+use std::{path::PathBuf, time::SystemTime};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+    task,
+};
+
+/// Hands entries to the task writing the trace file. Cheap to clone.
+#[derive(Clone)]
+pub(crate) struct Tracer {
+    entries: mpsc::UnboundedSender<TraceEntry>,
+}
+
+impl Tracer {
+    /// Starts writing a trace into `dir`. The task finishes once every tracer
+    /// is dropped and the trace is flushed.
+    pub(crate) fn spawn(dir: PathBuf) -> (Self, task::JoinHandle<()>) {
+        let (entries, rx) = mpsc::unbounded_channel();
+        (Self { entries }, tokio::spawn(write_trace(dir, rx)))
+    }
+
+    pub(crate) fn record(&self, direction: Direction, line: &[u8]) {
+        let message = serde_json::from_slice(line)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(line).into_owned()));
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let _ = self.entries.send(TraceEntry {
+            time,
+            direction,
+            message,
+        });
+    }
+}
+
+/// Buffers entries until `init` names the node, then writes them all to
+/// `<dir>/<node id>.jsonl`.
+async fn write_trace(dir: PathBuf, mut entries: mpsc::UnboundedReceiver<TraceEntry>) {
+    let mut pending = Vec::new();
+    let node_id = loop {
+        let Some(entry) = entries.recv().await else {
+            return;
+        };
+
+        let node_id = match (&entry.direction, &entry.message["body"]) {
+            (Direction::In, body) if body["type"] == "init" => {
+                body["node_id"].as_str().map(str::to_string)
+            }
+            _ => None,
+        };
+        pending.push(entry);
+
+        if let Some(node_id) = node_id {
+            break node_id;
+        }
+    };
+
+    if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+        eprintln!("could not create trace directory {}: {e}", dir.display());
+        return;
+    }
+
+    let path = dir.join(format!("{node_id}.jsonl"));
+    let mut file = match File::create(&path).await {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => {
+            eprintln!("could not create trace {}: {e}", path.display());
+            return;
+        }
+    };
+
+    for entry in pending {
+        if let Err(e) = write_entry(&mut file, &entry).await {
+            eprintln!("could not write trace: {e}");
+            return;
+        }
+    }
+
+    while let Some(entry) = entries.recv().await {
+        if let Err(e) = write_entry(&mut file, &entry).await {
+            eprintln!("could not write trace: {e}");
+            return;
+        }
+
+        // flush whenever we catch up, so a killed node leaves a usable trace
+        if entries.is_empty() {
+            if let Err(e) = file.flush().await {
+                eprintln!("could not write trace: {e}");
+                return;
+            }
+        }
+    }
+
+    let _ = file.flush().await;
+}
+
+async fn write_entry(file: &mut BufWriter<File>, entry: &TraceEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    file.write_all(&line).await
+}
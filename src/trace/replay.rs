@@ -0,0 +1,324 @@
+use super::{Direction, TraceEntry};
+use crate::{actors, maelstrom_protocol};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf};
+
+/// How long to wait for an output the recording says should have come.
+const REPLAY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// An output that differs from the recording. `None` on either side means
+/// the output was missing there.
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    /// The position of the recorded output, or of the replayed one when
+    /// nothing was recorded for it.
+    pub index: usize,
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub inputs: usize,
+    pub outputs: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ReplayReport {
+    pub fn is_valid(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl std::fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "replayed {} inputs, {} outputs, {} differ",
+            self.inputs,
+            self.outputs,
+            self.mismatches.len()
+        )?;
+        for mismatch in &self.mismatches {
+            writeln!(f, "output {}:", mismatch.index)?;
+            writeln!(f, "  recorded: {}", describe(&mismatch.expected))?;
+            writeln!(f, "  replayed: {}", describe(&mismatch.actual))?;
+        }
+        Ok(())
+    }
+}
+
+/// Feeds a node the inputs of a recorded trace and compares what it writes
+/// with the recorded outputs. Outputs are paired by destination and
+/// `in_reply_to`, or by destination and type when they are not replies, so
+/// they may come in another order. `msg_id`s are not compared.
+///
+/// Before each input, the node gets a moment to write whatever it wrote
+/// before that input in the recording. Periodic tasks are not run, so
+/// outputs that came from them show up as missing. Tracing and faults are
+/// off whatever the environment says.
+pub async fn replay<A, P, F>(trace: &[TraceEntry], make_node: F) -> ReplayReport
+where
+    A: xtra::Actor + xtra::Handler<actors::Input<P>>,
+    P: maelstrom_protocol::Payload + 'static,
+    F: FnOnce(actors::NodeContext, &actors::Runtime) -> A + Send + 'static,
+{
+    let (node_end, replay_end) = io::duplex(1 << 20);
+    let (output, mut input) = io::split(replay_end);
+    let node = tokio::spawn(
+        actors::Runtime::with_transport(node_end)
+            .trace(None)
+            .faults(Vec::new())
+            .fault_control(false)
+            .node(make_node)
+            .run(),
+    );
+
+    let mut output = BufReader::new(output).lines();
+    let mut actual = Vec::new();
+    let mut report = ReplayReport::default();
+
+    for entry in trace {
+        match entry.direction {
+            Direction::Out => report.outputs += 1,
+            Direction::In => {
+                read_until(&mut output, &mut actual, report.outputs).await;
+
+                let mut line = match &entry.message {
+                    Value::String(line) => line.clone().into_bytes(),
+                    message => serde_json::to_vec(message).expect("could not serialize message"),
+                };
+                line.push(b'\n');
+                if input.write_all(&line).await.is_err() {
+                    break;
+                }
+                report.inputs += 1;
+            }
+        }
+    }
+
+    read_until(&mut output, &mut actual, report.outputs).await;
+    let _ = input.shutdown().await;
+    while let Ok(Some(line)) = output.next_line().await {
+        actual.push(parse(&line));
+    }
+    let _ = node.await;
+
+    let expected: Vec<_> = trace
+        .iter()
+        .filter(|entry| entry.direction == Direction::Out)
+        .map(|entry| normalize(&entry.message))
+        .collect();
+    let mut unmatched: Vec<_> = actual.iter().map(normalize).enumerate().collect();
+
+    for (index, expected) in expected.into_iter().enumerate() {
+        let key = pairing_key(&expected);
+        let same = unmatched.iter().position(|(_, actual)| *actual == expected);
+        let similar = || {
+            unmatched
+                .iter()
+                .position(|(_, actual)| pairing_key(actual) == key)
+        };
+
+        match same.or_else(similar) {
+            Some(i) => {
+                let (_, actual) = unmatched.remove(i);
+                if actual != expected {
+                    report.mismatches.push(Mismatch {
+                        index,
+                        expected: Some(expected),
+                        actual: Some(actual),
+                    });
+                }
+            }
+            None => report.mismatches.push(Mismatch {
+                index,
+                expected: Some(expected),
+                actual: None,
+            }),
+        }
+    }
+
+    report
+        .mismatches
+        .extend(unmatched.into_iter().map(|(index, actual)| Mismatch {
+            index,
+            expected: None,
+            actual: Some(actual),
+        }));
+
+    report
+}
+
+/// Reads outputs until there are `count` of them, or the node goes quiet.
+async fn read_until(
+    output: &mut Lines<BufReader<ReadHalf<DuplexStream>>>,
+    actual: &mut Vec<Value>,
+    count: usize,
+) {
+    while actual.len() < count {
+        match tokio::time::timeout(REPLAY_TIMEOUT, output.next_line()).await {
+            Ok(Ok(Some(line))) => actual.push(parse(&line)),
+            _ => return,
+        }
+    }
+}
+
+/// An output without its `msg_id`, which depends on everything the node sent
+/// before it.
+fn normalize(message: &Value) -> Value {
+    let mut message = message.clone();
+    if let Some(body) = message.get_mut("body").and_then(Value::as_object_mut) {
+        body.remove("msg_id");
+    }
+    message
+}
+
+/// What a recorded and a replayed output must share to be compared.
+fn pairing_key(message: &Value) -> (&Value, &Value) {
+    let body = &message["body"];
+    match &body["in_reply_to"] {
+        Value::Null => (&message["dest"], &body["type"]),
+        in_reply_to => (&message["dest"], in_reply_to),
+    }
+}
+
+fn parse(line: &str) -> Value {
+    serde_json::from_str(line).unwrap_or_else(|_| Value::String(line.to_string()))
+}
+
+fn describe(message: &Option<Value>) -> String {
+    match message {
+        Some(message) => message.to_string(),
+        None => "nothing".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[serde(tag = "type")]
+    enum Payload {
+        Echo { echo: String },
+        EchoOk { echo: String },
+        Note { echo: String },
+    }
+
+    impl maelstrom_protocol::Payload for Payload {}
+
+    /// Echoes, and tells `n2` about it first.
+    struct Echo {
+        shout: bool,
+    }
+
+    impl xtra::Actor for Echo {}
+
+    #[async_trait::async_trait]
+    impl xtra::Handler<actors::Input<Payload>> for Echo {
+        async fn handle(
+            &mut self,
+            actors::Input(message, cx): actors::Input<Payload>,
+            _ctx: &mut xtra::Context<Self>,
+        ) {
+            if let Payload::Echo { mut echo } = message.body.payload {
+                if self.shout {
+                    echo = echo.to_uppercase();
+                }
+                cx.send("n2", Payload::Note { echo: echo.clone() });
+                cx.reply(Payload::EchoOk { echo });
+            }
+        }
+    }
+
+    /// Runs an echo node on a few requests and returns its trace.
+    async fn record() -> Vec<TraceEntry> {
+        static RECORDINGS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let recording = RECORDINGS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let dir =
+            std::env::temp_dir().join(format!("glomers-replay-{}-{recording}", std::process::id()));
+        let (node_end, recorder_end) = io::duplex(1 << 16);
+        let (output, mut input) = io::split(recorder_end);
+        let node = tokio::spawn(
+            actors::Runtime::with_transport(node_end)
+                .trace(Some(dir.clone()))
+                .faults(Vec::new())
+                .fault_control(false)
+                .node(|_, _| Echo { shout: false })
+                .run(),
+        );
+
+        let mut lines = vec![
+            json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1", "n2"]}}),
+        ];
+        lines.extend((0..3).map(|i| {
+            json!({"src": "c1", "dest": "n1", "body": {"type": "echo", "msg_id": i + 1, "echo": format!("hi {i}")}})
+        }));
+        for line in lines {
+            let mut line = serde_json::to_vec(&line).unwrap();
+            line.push(b'\n');
+            input.write_all(&line).await.unwrap();
+        }
+        input.shutdown().await.unwrap();
+
+        let mut output = BufReader::new(output).lines();
+        while let Ok(Some(_)) = output.next_line().await {}
+        node.await.unwrap();
+
+        let trace = crate::trace::read(dir.join("n1.jsonl")).unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+        trace
+    }
+
+    #[tokio::test]
+    async fn unchanged_node_matches_its_trace() {
+        let trace = record().await;
+        assert_eq!(trace.len(), 1 + 3 + 1 + 6);
+
+        let report = replay(&trace, |_, _| Echo { shout: false }).await;
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.inputs, 4);
+        assert_eq!(report.outputs, 7);
+    }
+
+    #[tokio::test]
+    async fn order_and_msg_ids_do_not_matter() {
+        let mut trace = record().await;
+
+        // swap the first note with its echo_ok, and renumber every output
+        let first = trace
+            .iter()
+            .position(|entry| entry.message["body"]["type"] == "note")
+            .unwrap();
+        trace.swap(first, first + 1);
+        for entry in &mut trace {
+            if entry.direction == Direction::Out {
+                entry.message["body"]["msg_id"] = json!(100);
+            }
+        }
+
+        let report = replay(&trace, |_, _| Echo { shout: false }).await;
+        assert!(report.is_valid(), "{report}");
+    }
+
+    #[tokio::test]
+    async fn changed_node_is_reported() {
+        let trace = record().await;
+
+        let report = replay(&trace, |_, _| Echo { shout: true }).await;
+        assert_eq!(report.mismatches.len(), 6);
+
+        let mismatch = &report.mismatches[1];
+        assert_eq!(
+            mismatch.expected.as_ref().unwrap()["body"]["type"],
+            "echo_ok"
+        );
+        assert_eq!(mismatch.actual.as_ref().unwrap()["body"]["echo"], "HI 0");
+        assert_eq!(mismatch.actual.as_ref().unwrap()["body"]["in_reply_to"], 1);
+    }
+}
@@ -0,0 +1,4 @@
+//! Nodes the binaries run, kept in the library so they can be run through
+//! [`crate::sim`] and the [`crate::check`]ers.
+
+pub mod broadcast;
@@ -0,0 +1,186 @@
+//! Broadcast that gossips everything it has to its neighbours on every tick,
+//! so messages lost in between are sent again on the next one.
+
+use crate::{actors, maelstrom_protocol};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub struct BroadcastNode {
+    node: actors::NodeContext,
+    messages: HashSet<usize>,
+    neighbours: HashSet<String>,
+    sender: xtra::WeakAddress<actors::Sender>,
+}
+
+impl BroadcastNode {
+    pub fn new(node: actors::NodeContext, sender: xtra::WeakAddress<actors::Sender>) -> Self {
+        Self {
+            node,
+            messages: HashSet::new(),
+            neighbours: HashSet::new(),
+            sender,
+        }
+    }
+}
+
+impl xtra::Actor for BroadcastNode {}
+
+/// Sends the node's messages to its neighbours.
+pub struct Gossip;
+
+impl xtra::Message for Gossip {
+    type Result = ();
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "type")]
+pub enum Payload {
+    Broadcast {
+        message: usize,
+    },
+    BroadcastOk,
+    Read,
+    ReadOk {
+        messages: HashSet<usize>,
+    },
+    Topology {
+        topology: HashMap<String, HashSet<String>>,
+    },
+    TopologyOk,
+    Gossip {
+        messages: HashSet<usize>,
+    },
+}
+
+impl maelstrom_protocol::Payload for Payload {}
+
+#[async_trait::async_trait]
+impl xtra::Handler<Gossip> for BroadcastNode {
+    async fn handle(&mut self, _: Gossip, _ctx: &mut xtra::Context<Self>) {
+        for n in &self.neighbours {
+            let message = maelstrom_protocol::Message::new(
+                self.node.node_id.clone(),
+                n.clone(),
+                Payload::Gossip {
+                    messages: self.messages.clone(),
+                },
+            );
+
+            // the writer only goes away once the node is shutting down
+            if self.sender.do_send(actors::Output(message)).is_err() {
+                eprintln!("could not gossip: {}", actors::Error::WriterStopped);
+                return;
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl xtra::Handler<actors::Input<Payload>> for BroadcastNode {
+    async fn handle(
+        &mut self,
+        actors::Input(message, cx): actors::Input<Payload>,
+        _ctx: &mut xtra::Context<Self>,
+    ) {
+        match &message.body.payload {
+            Payload::Broadcast { message: m } => {
+                self.messages.insert(*m);
+
+                cx.reply(Payload::BroadcastOk);
+            }
+            Payload::Read => cx.reply(Payload::ReadOk {
+                messages: self.messages.clone(),
+            }),
+            Payload::Topology { topology } => {
+                self.neighbours.extend(topology[&self.node.node_id].clone());
+
+                cx.reply(Payload::TopologyOk);
+            }
+
+            Payload::Gossip { messages } => {
+                self.messages.extend(messages.clone());
+            }
+
+            Payload::BroadcastOk | Payload::ReadOk { .. } | Payload::TopologyOk => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        check::{check_broadcast, BroadcastOp},
+        history::History,
+        sim::Simulation,
+    };
+    use std::time::Duration;
+
+    const GOSSIP_PERIOD: Duration = Duration::from_millis(100);
+
+    /// Broadcasts to every node of a line of three, lets the gossip settle and
+    /// reads every node back.
+    async fn run(loss: f64) -> History<BroadcastOp> {
+        let mut sim = Simulation::builder(7)
+            .nodes(3)
+            .latency(Duration::from_millis(1), Duration::from_millis(20))
+            .loss(loss)
+            .duplication(0.1)
+            .start(|runtime| async move {
+                runtime
+                    .node(|node, runtime| BroadcastNode::new(node, runtime.sender()))
+                    .every(GOSSIP_PERIOD, || Gossip)
+                    .run()
+                    .await
+            });
+
+        let topology = HashMap::from([
+            ("n1".to_string(), HashSet::from(["n2".to_string()])),
+            (
+                "n2".to_string(),
+                HashSet::from(["n1".to_string(), "n3".to_string()]),
+            ),
+            ("n3".to_string(), HashSet::from(["n2".to_string()])),
+        ]);
+        for node in sim.node_ids().to_vec() {
+            sim.request(
+                "c1",
+                &node,
+                Payload::Topology {
+                    topology: topology.clone(),
+                },
+            );
+            sim.recv().await.expect("topology is answered");
+        }
+
+        for message in 0..9 {
+            let node = format!("n{}", message % 3 + 1);
+            sim.request("c2", &node, Payload::Broadcast { message });
+            sim.recv().await.expect("broadcast is answered");
+        }
+        tokio::time::sleep(GOSSIP_PERIOD * 20).await;
+        for node in sim.node_ids().to_vec() {
+            sim.request("c3", &node, Payload::Read);
+            sim.recv().await.expect("read is answered");
+        }
+
+        let history = History::from_messages(sim.client_log().to_vec());
+        sim.shutdown().await;
+        history
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn every_node_reads_every_broadcast() {
+        let report = check_broadcast(&run(0.0).await);
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.acknowledged.len(), 9);
+    }
+
+    #[tokio::test(flavor = "current_thread", start_paused = true)]
+    async fn gossip_outlasts_a_lossy_network() {
+        let report = check_broadcast(&run(0.3).await);
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.acknowledged.len(), 9);
+    }
+}